@@ -0,0 +1,125 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::path::{Component, Path, PathBuf};
+use syn::{Error, LitStr};
+
+pub fn expand(pattern: LitStr) -> syn::Result<TokenStream> {
+    let root = match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(v) => PathBuf::from(v),
+        None => {
+            return Err(Error::new_spanned(
+                &pattern,
+                "CARGO_MANIFEST_DIR is not set",
+            ))
+        }
+    };
+
+    // Find the files.
+    let value = pattern.value();
+    let base = root.join(base(Path::new(&value)));
+    let full = root.join(&value);
+    let paths = match full.to_str().map(glob::glob) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => return Err(Error::new_spanned(&pattern, e)),
+        None => {
+            return Err(Error::new_spanned(
+                &pattern,
+                "the path is not a valid UTF-8",
+            ))
+        }
+    };
+
+    let mut chunks = Vec::new();
+
+    for path in paths {
+        let path = path.map_err(|e| Error::new_spanned(&pattern, e))?;
+
+        if !path.is_file() {
+            continue;
+        }
+
+        let name = match name(&base, &path) {
+            Some(v) => v,
+            None => {
+                return Err(Error::new_spanned(
+                    &pattern,
+                    format!("cannot determine the module name for {}", path.display()),
+                ))
+            }
+        };
+
+        let relative = path.strip_prefix(&root).unwrap_or(&path);
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let absolute = path.to_string_lossy().into_owned();
+
+        chunks.push((name, relative, absolute));
+    }
+
+    if chunks.is_empty() {
+        return Err(Error::new_spanned(&pattern, "no files matched the pattern"));
+    }
+
+    chunks.sort();
+
+    for pair in chunks.windows(2) {
+        if pair[0].0 == pair[1].0 {
+            return Err(Error::new_spanned(
+                &pattern,
+                format!("{} and {} have the same module name", pair[0].1, pair[1].1),
+            ));
+        }
+    }
+
+    let names = chunks.iter().map(|c| &c.0);
+    let paths = chunks.iter().map(|c| &c.1);
+    let sources = chunks.iter().map(|c| &c.2);
+
+    Ok(quote! {
+        &[#(locenv::EmbeddedChunk { name: #names, path: #paths, source: include_str!(#sources) }),*]
+    })
+}
+
+/// Gets the leading components of `pattern` that does not contain any wildcard.
+fn base(pattern: &Path) -> PathBuf {
+    let mut base = PathBuf::new();
+
+    for component in pattern.components() {
+        let value = component.as_os_str().to_string_lossy();
+
+        if value.contains(['*', '?', '[']) {
+            break;
+        }
+
+        base.push(component);
+    }
+
+    // The pattern is a path to a file.
+    if base == pattern {
+        base.pop();
+    }
+
+    base
+}
+
+/// Gets the name of the submodule from a path relative to `base` (e.g. `net/http.lua` to `net.http`).
+fn name(base: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?.with_extension("");
+    let mut name = Vec::new();
+
+    for component in relative.components() {
+        match component {
+            Component::Normal(v) => name.push(v.to_str()?.to_owned()),
+            _ => return None,
+        }
+    }
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.join("."))
+    }
+}
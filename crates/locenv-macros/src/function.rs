@@ -0,0 +1,169 @@
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{Error, FnArg, Ident, ItemFn, ReturnType, Signature, Type};
+
+pub fn expand(mut item: ItemFn) -> syn::Result<TokenStream> {
+    check_signature(&item.sig)?;
+
+    // Move the attributes to the generated function so the documentation is preserved.
+    let attrs = std::mem::take(&mut item.attrs);
+    let vis = std::mem::replace(&mut item.vis, syn::Visibility::Inherited);
+    let name = item.sig.ident.clone();
+    let (args, values) = expand_arguments(&mut item.sig, 1)?;
+    let body = expand_call(&item.sig, quote! { #name(#(#values),*) });
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis extern "C" fn #name(lua: *mut locenv::api::LuaState) -> std::os::raw::c_int {
+            #item
+
+            locenv::invoke(lua, locenv::upvalue_index(1), |context| {
+                #args
+                #body
+            })
+        }
+    })
+}
+
+/// Returns an error if the function cannot be exposed to Lua.
+pub fn check_signature(sig: &Signature) -> syn::Result<()> {
+    if let Some(v) = &sig.asyncness {
+        return Err(Error::new_spanned(v, "async function is not supported"));
+    }
+
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "generic function is not supported",
+        ));
+    }
+
+    if let Some(v) = &sig.variadic {
+        return Err(Error::new_spanned(v, "variadic function is not supported"));
+    }
+
+    Ok(())
+}
+
+/// Generates a code to convert Lua arguments to the arguments of the function, starting at Lua argument `first`.
+///
+/// The generated code require `lua` and `context` to be in the scope. Returns the generated code and the expressions
+/// to pass to the function. The #[state] attributes are removed from `sig`.
+pub fn expand_arguments(
+    sig: &mut Signature,
+    first: i32,
+) -> syn::Result<(TokenStream, Vec<TokenStream>)> {
+    let mut code = quote! { let index: std::os::raw::c_int = #first; };
+    let mut values = Vec::new();
+
+    for (i, arg) in sig.inputs.iter_mut().enumerate() {
+        let arg = match arg {
+            FnArg::Receiver(_) => continue,
+            FnArg::Typed(v) => v,
+        };
+
+        // Take #[state] out since it is not a real attribute.
+        let state = match arg.attrs.iter().position(|a| a.path.is_ident("state")) {
+            Some(i) => Some(arg.attrs.remove(i)),
+            None => None,
+        };
+
+        let ty = &arg.ty;
+
+        if let Some(attr) = state {
+            let state = match ty.as_ref() {
+                Type::Reference(r) if r.mutability.is_some() => &r.elem,
+                _ => {
+                    return Err(Error::new_spanned(
+                        attr,
+                        "#[state] parameter must be &mut T",
+                    ))
+                }
+            };
+
+            let name = Ident::new(&format!("arg{}", i), Span::call_site());
+
+            code.extend(quote! {
+                let mut #name = context.state::<#state>()?;
+            });
+
+            values.push(quote! { &mut *#name });
+        } else if is_context(ty) {
+            values.push(quote! { context });
+        } else if is_lua(ty) {
+            values.push(quote! { lua });
+        } else if is_mut_ref(ty) {
+            return Err(Error::new_spanned(
+                ty,
+                "mutable reference is not supported, use #[state] if this is the module state",
+            ));
+        } else {
+            let name = Ident::new(&format!("arg{}", i), Span::call_site());
+
+            code.extend(quote! {
+                let #name = <#ty as locenv::FromLua>::from_lua(lua, index)?;
+                let index = index + <#ty as locenv::FromLua>::SLOTS;
+            });
+
+            values.push(quote! { #name });
+        }
+    }
+
+    code.extend(quote! { let _ = index; });
+
+    Ok((code, values))
+}
+
+/// Generates a code to invoke the function with `call` and push its result.
+///
+/// The generated code evaluate to `Result<c_int, LuaError>`.
+pub fn expand_call(sig: &Signature, call: TokenStream) -> TokenStream {
+    match &sig.output {
+        ReturnType::Default => quote! {
+            #call;
+            Ok(0)
+        },
+        ReturnType::Type(_, ty) => {
+            if is_result(ty) {
+                quote! {
+                    let result = #call?;
+                    locenv::Return::into_lua(result, lua)
+                }
+            } else {
+                quote! { locenv::Return::into_lua(#call, lua) }
+            }
+        }
+    }
+}
+
+fn is_context(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(r) => is_path(&r.elem, "Context"),
+        _ => false,
+    }
+}
+
+fn is_mut_ref(ty: &Type) -> bool {
+    matches!(ty, Type::Reference(r) if r.mutability.is_some())
+}
+
+fn is_lua(ty: &Type) -> bool {
+    match ty {
+        Type::Ptr(p) => p.mutability.is_some() && is_path(&p.elem, "LuaState"),
+        _ => false,
+    }
+}
+
+fn is_result(ty: &Type) -> bool {
+    is_path(ty, "Result")
+}
+
+fn is_path(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(p) => match p.path.segments.last() {
+            Some(v) => v.ident == name,
+            None => false,
+        },
+        _ => false,
+    }
+}
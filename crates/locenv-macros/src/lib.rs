@@ -1,6 +1,12 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, ItemFn};
+use syn::{parse_macro_input, ItemFn, ItemImpl, ItemMod, LitStr};
+
+mod embed;
+mod function;
+mod loader;
+mod module;
+mod object;
+mod test;
 
 /// Specify that the function is a module loader.
 ///
@@ -10,58 +16,268 @@ use syn::{parse_macro_input, ItemFn};
 ///
 /// ```no_run
 /// use locenv::api::LuaState;
+/// use locenv::LuaError;
 /// use locenv_macros::loader;
 /// use std::os::raw::c_int;
 ///
 /// #[loader]
-/// extern "C" fn loader(lua: *mut LuaState) -> c_int {
-///     0
+/// fn loader(lua: *mut LuaState) -> Result<c_int, LuaError> {
+///     Ok(0)
 /// }
 /// ```
+///
+/// The function is invoked through a generated `extern "C"` function, which raises the returned error or a panic as a
+/// Lua error. Any ABI specified on the function will be removed.
+///
+/// Use `#[loader(state = T)]` to attach a state to the module, where `T` must implement `Default`. The state is created
+/// when the module is bootstrapped and dropped when the Lua VM is closed, which make it suitable for the data that
+/// should not be shared between multiple Lua VMs. Use `Context::state()` to access it.
 #[proc_macro_attribute]
-pub fn loader(_: TokenStream, item: TokenStream) -> TokenStream {
+pub fn loader(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as loader::Args);
     let input = parse_macro_input!(item as ItemFn);
-    let loader = &input.sig.ident;
-    let result = quote! {
-        #input
 
-        #[no_mangle]
-        pub unsafe extern "C" fn bootstrap(bootstrap: *const locenv::api::BootstrapContext, api: *const locenv::api::ApiTable) -> std::os::raw::c_int {
-            if locenv::API_TABLE.is_null() {
-                locenv::API_TABLE = api;
-            }
+    loader::expand(args, input).into()
+}
 
-            let lua = (*bootstrap).lua;
-            let context = locenv::Context::new(bootstrap);
+/// Generates a Lua function from a Rust function.
+///
+/// The function will be replaced with an `extern "C"` function with the same name, which can be used with
+/// [`FunctionEntry`](https://docs.rs/locenv/latest/locenv/struct.FunctionEntry.html). The generated function converts
+/// Lua arguments with `FromLua` and pushes the return value with `Return`. A parameter with `&Context` type receives
+/// the context on the first upvalue and a parameter with `*mut LuaState` type receives the current Lua state; neither
+/// of them consumes a Lua argument. A `&mut T` parameter with #\[state\] attribute receives the module state from
+/// `Context::state()`, any other `&mut` parameter is rejected. The return value can be either a value or `Result` with
+/// an error type that can be converted to `LuaError`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use locenv::{Context, LuaError};
+/// use locenv_macros::function;
+///
+/// #[function]
+/// fn join(ctx: &Context, a: String, b: Option<i64>) -> Result<String, LuaError> {
+///     match b {
+///         Some(b) => Ok(format!("{}{}", a, b)),
+///         None => Ok(a),
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn function(_: TokenStream, item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as ItemFn);
 
-            locenv::push_fn(lua, #loader, 0);
+    match function::expand(input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
 
-            // Move context to user data.
-            let raw = Box::into_raw(Box::new(context));
-            let ptr = std::mem::size_of::<*mut locenv::Context>();
-            let ud = ((*api).lua_newuserdatauv)(lua, ptr, 1);
+/// Generates a module loader from an inline module.
+///
+/// The following items inside the module will be exported to the module table:
+///
+/// - Functions that have #\[function\] attribute.
+/// - Public constants, which the type must implement `ToLua`.
+/// - Types that implement `Object`, including the one with #\[object\] attribute. The table from
+///   `locenv::push_methods()` will be exported with the name from `UserData::type_name()`.
+/// - Lua files from `embed_lua!` invocations, which will be registered as the submodules.
+///
+/// An inline module inside the module that has #\[module\] attribute will become a submodule with its own table, which
+/// can be loaded with `require '<module>.<submodule>'`. The submodules are registered to `package.preload` when the
+/// module is bootstrapped and share the same context with the parent. There is no need to import #\[module\] for the
+/// nested modules.
+///
+/// A #\[loader\] will be generated inside the module so do not define another one.
+///
+/// Use `#[module(state = T)]` to attach a state to the module, the same as `#[loader(state = T)]`. The state is shared
+/// with the submodules.
+///
+/// # Examples
+///
+/// ```no_run
+/// use locenv_macros::module;
+///
+/// #[module]
+/// mod mymodule {
+///     use locenv::{Context, LuaError};
+///     use locenv_macros::function;
+///
+///     pub const VERSION: &str = "1.0.0";
+///
+///     #[function]
+///     fn greet(ctx: &Context, name: String) -> Result<String, LuaError> {
+///         Ok(format!("Hello, {}! This is {}.", name, ctx.module_name()))
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn module(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as loader::Args);
+    let input = parse_macro_input!(item as ItemMod);
 
-            ud.copy_from_nonoverlapping(std::mem::transmute(&raw), ptr);
+    match module::expand(args, input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
 
-            // Associate the userdata with metatable.
-            if ((*api).aux_newmetatable)(lua, (*bootstrap).name) == 0 {
-                let context = Box::from_raw(raw);
+/// Implements `Object` from an `impl` block.
+///
+/// All methods that take `&self` or `&mut self` will become the methods of the object. The arguments and the return
+/// value are converted the same way as #\[function\], except the first Lua argument is the object itself so the first
+/// parameter of the method receives the second Lua argument. The methods without `self` are not exported so use
+/// another `impl` block for the methods that should not be exported.
+///
+/// `Return` is also implemented for the type so it can be returned from #\[function\] and the methods, which will push
+/// it as a new userdata. This includes `Option`, `Vec` and the tuples of the type.
+///
+/// The object is borrowed while the method is running, shared for `&self` and exclusive for `&mut self`. If the method
+/// calls back into Lua and Lua invokes a conflicting method on the same object, that call will fail with "object
+/// already borrowed" as a Lua error.
+///
+/// A method with `#[meta(event)]` will become a metamethod instead, where `event` is the name of the event without
+/// `__` (e.g. `tostring`, `eq`, `len`, `call` or `add`). For the binary events, the first parameter receives the other
+/// operand and the second parameter (if any) receives a `bool` that indicates whether the object was the right
+/// operand.
+///
+/// A method with `#[getter]` or `#[setter]` will become an accessor of the property with the same name as the method
+/// instead. The `set_` prefix is removed from the name of the setter. Use `#[getter(name)]` or `#[setter(name)]` to
+/// specify the name of the property explicitly. The setter receives the new value in its first parameter.
+///
+/// Use `#[object(user_values = N)]` to reserve `N` user values on the userdata (see `Object::USER_VALUES`).
+///
+/// # Examples
+///
+/// ```no_run
+/// use locenv::{LuaValue, UserData};
+/// use locenv_macros::object;
+///
+/// struct Person {
+///     name: String,
+/// }
+///
+/// impl UserData for Person {
+///     fn type_name() -> &'static str {
+///         "Person"
+///     }
+/// }
+///
+/// #[object]
+/// impl Person {
+///     #[getter]
+///     fn name(&self) -> String {
+///         self.name.clone()
+///     }
+///
+///     #[setter]
+///     fn set_name(&mut self, name: String) {
+///         self.name = name;
+///     }
+///
+///     #[meta(tostring)]
+///     fn to_string(&self) -> String {
+///         format!("Person({})", self.name)
+///     }
+///
+///     #[meta(eq)]
+///     fn equals(&self, other: LuaValue) -> bool {
+///         false
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn object(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as object::Args);
+    let input = parse_macro_input!(item as ItemImpl);
 
-                locenv::pop(lua, 3); // Pop metatable + user data + loader.
-                locenv::push_str(lua, &format!("someone already created a metatable named '{}'", context.module_name()));
+    match object::expand(args, input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
 
-                return 1;
-            }
+/// Generates a test that runs a module on a fresh Lua VM from `locenv-test` crate.
+///
+/// The argument is a path to the module that contains `bootstrap` function generated by #\[module\] or #\[loader\]. The
+/// module will be loaded and required into a global variable with the same name as the last segment of the path. Use
+/// `name = "..."` to specify the module name explicitly, which is required if the path is `crate`.
+///
+/// If the body of the function is a single string literal it will be run as a Lua chunk. Otherwise the body will be
+/// run as a normal Rust test, which can take `&locenv_test::Host` as a parameter. Any Lua error, including a failed
+/// `assert`, will fail the test with the error message and the traceback.
+///
+/// # Examples
+///
+/// ```no_run
+/// use locenv_macros::{locenv_test, module};
+/// use locenv_test::Host;
+///
+/// #[module]
+/// mod mymodule {
+///     use locenv_macros::function;
+///
+///     #[function]
+///     fn greet(name: String) -> String {
+///         format!("Hello, {}!", name)
+///     }
+/// }
+///
+/// #[locenv_test(mymodule)]
+/// fn greet() {
+///     "assert(mymodule.greet('John') == 'Hello, John!')"
+/// }
+///
+/// #[locenv_test(mymodule)]
+/// fn greet_from_rust(host: &Host) {
+///     let result: String = host.eval("return mymodule.greet('John')").unwrap();
+///
+///     assert_eq!(result, "Hello, John!");
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn locenv_test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as test::Args);
+    let input = parse_macro_input!(item as ItemFn);
 
-            ((*api).lua_pushstring)(lua, b"__gc\0".as_ptr() as *const _);
-            ((*api).lua_pushstring)(lua, (*bootstrap).name);
-            ((*api).lua_pushcclosure)(lua, locenv::Context::finalize, 1);
-            ((*api).lua_settable)(lua, -3);
-            ((*api).lua_setmetatable)(lua, -2);
+    match test::expand(args, input) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
 
-            2
-        }
-    };
+/// Embeds Lua files into the module at compile time.
+///
+/// The argument is a glob pattern relative to the directory that contains `Cargo.toml`. Each file will become a
+/// submodule with the name derived from its path relative to the part of the pattern before the first wildcard (e.g.
+/// `lua/net/http.lua` from `lua/**/*.lua` will become `mymodule.net.http`).
+///
+/// Put the invocation inside a module with #\[module\] attribute and the files will be registered to
+/// `package.preload` when the module is loaded, which means `require 'mymodule.helpers'` can be used after
+/// `require 'mymodule'`. Outside #\[module\] this macro evaluates to `&'static [locenv::EmbeddedChunk]`, which can
+/// be passed to `locenv::preload_chunks()`.
+///
+/// Please note that adding a new file that matches the pattern will not trigger a rebuild.
+///
+/// # Examples
+///
+/// ```ignore
+/// use locenv_macros::module;
+///
+/// #[module]
+/// mod mymodule {
+///     use locenv_macros::embed_lua;
+///
+///     embed_lua!("lua/*.lua");
+/// }
+/// ```
+#[proc_macro]
+pub fn embed_lua(input: TokenStream) -> TokenStream {
+    let pattern = parse_macro_input!(input as LitStr);
 
-    result.into()
+    match embed::expand(pattern) {
+        Ok(v) => v.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
 }
@@ -0,0 +1,129 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Error, Ident, ItemFn, Token, Type};
+
+/// Arguments of #[loader] and #[module].
+#[derive(Default)]
+pub struct Args {
+    pub state: Option<Type>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Self::default();
+
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+
+            if key != "state" {
+                return Err(Error::new_spanned(key, "unknown argument"));
+            }
+
+            input.parse::<Token![=]>()?;
+            args.state = Some(input.parse()?);
+
+            if input.parse::<Option<Token![,]>>()?.is_none() {
+                break;
+            }
+        }
+
+        if !input.is_empty() {
+            return Err(input.error("unexpected token"));
+        }
+
+        Ok(args)
+    }
+}
+
+pub fn expand(args: Args, input: ItemFn) -> TokenStream {
+    expand_with(args, input, TokenStream::new())
+}
+
+/// Generates a bootstrap for `input`. `setup` will be run on the bootstrap with `lua` and `context` (the index of the
+/// context userdata) in scope, which can return a `LuaError` with `?`.
+pub fn expand_with(args: Args, mut input: ItemFn, setup: TokenStream) -> TokenStream {
+    let loader = &input.sig.ident;
+    let state = match &args.state {
+        Some(t) => quote! { context.set_state(<#t as Default>::default()); },
+        None => quote! {},
+    };
+
+    input.sig.abi = None;
+
+    quote! {
+        #input
+
+        #[no_mangle]
+        pub unsafe extern "C" fn bootstrap(bootstrap: *const locenv::api::BootstrapContext, api: *const locenv::api::ApiTable) -> std::os::raw::c_int {
+            extern "C" fn __locenv_loader(lua: *mut locenv::api::LuaState) -> std::os::raw::c_int {
+                locenv::invoke(lua, 2, |_| #loader(lua))
+            }
+
+            if locenv::API_TABLE.is_null() {
+                locenv::API_TABLE = api;
+            }
+
+            let lua = (*bootstrap).lua;
+            let top = ((*api).lua_gettop)(lua);
+            let result = std::panic::catch_unwind(|| {
+                let context = locenv::Context::new(bootstrap);
+
+                #state
+
+                locenv::push_fn(lua, __locenv_loader, 0);
+
+                // Move context to user data.
+                let raw = Box::into_raw(Box::new(context));
+                let ptr = std::mem::size_of::<*mut locenv::Context>();
+                let ud = ((*api).lua_newuserdatauv)(lua, ptr, 1);
+
+                (ud as *mut *mut locenv::Context).write_unaligned(raw);
+
+                // Associate the userdata with metatable.
+                if ((*api).aux_newmetatable)(lua, (*bootstrap).name) == 0 {
+                    let context = Box::from_raw(raw);
+
+                    locenv::pop(lua, 3); // Pop metatable + user data + loader.
+                    locenv::push_str(lua, &format!("someone already created a metatable named '{}'", context.module_name()));
+
+                    return 1;
+                }
+
+                ((*api).lua_pushstring)(lua, b"__gc\0".as_ptr() as *const _);
+                ((*api).lua_pushstring)(lua, (*bootstrap).name);
+                ((*api).lua_pushcclosure)(lua, locenv::Context::finalize, 1);
+                ((*api).lua_settable)(lua, -3);
+                ((*api).lua_setmetatable)(lua, -2);
+
+                // Run additional setup.
+                #[allow(unused_variables)]
+                let context = ((*api).lua_gettop)(lua);
+                let setup = || -> Result<(), locenv::LuaError> {
+                    #setup
+                    Ok(())
+                };
+
+                if let Err(e) = setup() {
+                    ((*api).lua_settop)(lua, top);
+                    locenv::push_str(lua, &e.to_string());
+                    return 1;
+                }
+
+                2
+            });
+
+            match result {
+                Ok(v) => v,
+                Err(e) => {
+                    let name = std::ffi::CStr::from_ptr((*bootstrap).name).to_string_lossy();
+
+                    ((*api).lua_settop)(lua, top);
+                    locenv::push_str(lua, &format!("module '{}' panicked: {}", name, locenv::panic_message(&*e)));
+
+                    1
+                }
+            }
+        }
+    }
+}
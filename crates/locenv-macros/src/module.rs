@@ -0,0 +1,199 @@
+use crate::loader;
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{parse_quote, Error, Ident, Item, ItemMod, Macro, Type, Visibility};
+
+pub fn expand(args: loader::Args, mut input: ItemMod) -> syn::Result<TokenStream> {
+    let items = content(&mut input)?;
+    let exports = Exports::collect(items)?;
+
+    // Generate loader.
+    let loader = Ident::new("__locenv_module", Span::call_site());
+    let table = exports.table(quote! { 2 });
+    let chunks = &exports.chunks;
+    let submodules = &exports.submodules;
+    let loader = loader::expand_with(
+        args,
+        parse_quote! {
+            fn #loader(lua: *mut locenv::api::LuaState) -> Result<std::os::raw::c_int, locenv::LuaError> {
+                #table
+                Ok(1)
+            }
+        },
+        quote! {
+            #(locenv::preload_chunks(lua, context, #chunks)?;)*
+            #(#submodules::__locenv_register(lua, context, "")?;)*
+        },
+    );
+
+    items.push(Item::Verbatim(loader));
+
+    Ok(quote! { #input })
+}
+
+/// Generates the loader for the nested module with #[module] attribute, which will be registered to `package.preload`
+/// by the parent.
+fn expand_submodule(module: &mut ItemMod) -> syn::Result<()> {
+    let name = module.ident.to_string();
+    let items = content(module)?;
+    let exports = Exports::collect(items)?;
+
+    if let Some(v) = exports.chunks.first() {
+        return Err(Error::new_spanned(
+            v,
+            "embed_lua! is supported only on the top-level module",
+        ));
+    }
+
+    let table = exports.table(quote! { locenv::upvalue_index(1) });
+    let submodules = &exports.submodules;
+
+    items.push(Item::Verbatim(quote! {
+        #[doc(hidden)]
+        pub extern "C" fn __locenv_loader(lua: *mut locenv::api::LuaState) -> std::os::raw::c_int {
+            locenv::invoke(lua, locenv::upvalue_index(1), |_| {
+                #table
+                Ok(1)
+            })
+        }
+
+        #[doc(hidden)]
+        pub fn __locenv_register(lua: *mut locenv::api::LuaState, context: std::os::raw::c_int, prefix: &str) -> Result<(), locenv::LuaError> {
+            let name = format!("{}{}", prefix, #name);
+
+            locenv::preload_module(lua, context, &name, __locenv_loader)?;
+            #(#submodules::__locenv_register(lua, context, &format!("{}.", name))?;)*
+
+            Ok(())
+        }
+    }));
+
+    Ok(())
+}
+
+fn content(module: &mut ItemMod) -> syn::Result<&mut Vec<Item>> {
+    if module.content.is_none() {
+        return Err(Error::new_spanned(
+            &module,
+            "#[module] can only be applied on an inline module",
+        ));
+    }
+
+    Ok(&mut module.content.as_mut().unwrap().1)
+}
+
+struct Exports {
+    functions: Vec<Ident>,
+    constants: Vec<Ident>,
+    objects: Vec<Type>,
+    chunks: Vec<Macro>,
+    submodules: Vec<Ident>,
+}
+
+impl Exports {
+    fn collect(items: &mut Vec<Item>) -> syn::Result<Self> {
+        let mut exports = Self {
+            functions: Vec::new(),
+            constants: Vec::new(),
+            objects: Vec::new(),
+            chunks: Vec::new(),
+            submodules: Vec::new(),
+        };
+
+        // Take embed_lua! out since it is not a valid item.
+        items.retain(|item| match item {
+            Item::Macro(m) if is_path(&m.mac.path, "embed_lua") => {
+                exports.chunks.push(m.mac.clone());
+                false
+            }
+            _ => true,
+        });
+
+        for item in items.iter_mut() {
+            match item {
+                Item::Fn(f) if f.attrs.iter().any(|a| is_path(&a.path, "function")) => {
+                    exports.functions.push(f.sig.ident.clone());
+                }
+                Item::Const(c) if matches!(c.vis, Visibility::Public(_)) => {
+                    exports.constants.push(c.ident.clone());
+                }
+                Item::Impl(i) => {
+                    let object = match &i.trait_ {
+                        Some((_, path, _)) => is_path(path, "Object"),
+                        None => i.attrs.iter().any(|a| is_path(&a.path, "object")),
+                    };
+
+                    if object {
+                        exports.objects.push(i.self_ty.as_ref().clone());
+                    }
+                }
+                Item::Mod(m) => {
+                    // Take #[module] out so it will not expanded as a top-level module.
+                    let attr = match m.attrs.iter().position(|a| is_path(&a.path, "module")) {
+                        Some(i) => m.attrs.remove(i),
+                        None => continue,
+                    };
+
+                    if !attr.tokens.is_empty() {
+                        return Err(Error::new_spanned(
+                            attr,
+                            "submodule cannot have arguments because it shares the context with the parent",
+                        ));
+                    }
+
+                    expand_submodule(m)?;
+                    exports.submodules.push(m.ident.clone());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(exports)
+    }
+
+    /// Generates the code to push the module table. `context` is the index of the context.
+    fn table(&self, context: TokenStream) -> TokenStream {
+        let fields = (self.functions.len() + self.constants.len() + self.objects.len()) as i32;
+        let names: Vec<String> = self.functions.iter().map(|f| f.to_string()).collect();
+        let functions = &self.functions;
+        let functions = if functions.is_empty() {
+            quote! {}
+        } else {
+            let count = functions.len();
+
+            quote! {
+                const FUNCTIONS: [locenv::FunctionEntry; #count] = [#(locenv::FunctionEntry { name: #names, function: Some(#functions) }),*];
+
+                locenv::push_value(lua, #context);
+                locenv::set_functions(lua, &FUNCTIONS, 1);
+            }
+        };
+
+        let constants = &self.constants;
+        let constant_names: Vec<String> = constants.iter().map(|c| c.to_string()).collect();
+        let objects = self.objects.iter().map(|t: &Type| {
+            quote! {
+                locenv::push_methods::<#t>(lua, #context)?;
+                locenv::set_field(lua, -2, <#t as locenv::UserData>::type_name());
+            }
+        });
+
+        quote! {
+            locenv::create_table(lua, 0, #fields);
+
+            #functions
+            #(
+                locenv::ToLua::to_lua(#constants, lua);
+                locenv::set_field(lua, -2, #constant_names);
+            )*
+            #(#objects)*
+        }
+    }
+}
+
+fn is_path(path: &syn::Path, name: &str) -> bool {
+    match path.segments.last() {
+        Some(v) => v.ident == name,
+        None => false,
+    }
+}
@@ -0,0 +1,356 @@
+use crate::function::{check_signature, expand_arguments, expand_call};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::{Attribute, Error, FnArg, Ident, ImplItem, ItemImpl, LitInt, Token};
+
+/// Arguments of #[object].
+#[derive(Default)]
+pub struct Args {
+    user_values: Option<LitInt>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Self::default();
+
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+
+            if key != "user_values" {
+                return Err(Error::new_spanned(key, "unknown argument"));
+            }
+
+            input.parse::<Token![=]>()?;
+            args.user_values = Some(input.parse()?);
+
+            if input.parse::<Option<Token![,]>>()?.is_none() {
+                break;
+            }
+        }
+
+        if !input.is_empty() {
+            return Err(input.error("unexpected token"));
+        }
+
+        Ok(args)
+    }
+}
+
+pub fn expand(args: Args, mut input: ItemImpl) -> syn::Result<TokenStream> {
+    if let Some((_, path, _)) = &input.trait_ {
+        return Err(Error::new_spanned(
+            path,
+            "#[object] cannot be applied on a trait implementation",
+        ));
+    }
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "generic object is not supported",
+        ));
+    }
+
+    // Generate a wrapper for each method.
+    let ty = input.self_ty.clone();
+    let mut methods = Vec::new();
+    let mut metamethods = Vec::new();
+    let mut properties: Vec<Property> = Vec::new();
+
+    for item in &mut input.items {
+        let method = match item {
+            ImplItem::Method(v) => v,
+            _ => continue,
+        };
+
+        // Take #[meta] out since it is not a real attribute.
+        let meta = match method.attrs.iter().position(|a| a.path.is_ident("meta")) {
+            Some(i) => {
+                let attr = method.attrs.remove(i);
+                let event = attr.parse_args_with(Ident::parse_any)?;
+
+                Some(metamethod(&event)?)
+            }
+            None => None,
+        };
+
+        // Same for #[getter] and #[setter].
+        let accessor = take_accessor(&mut method.attrs)?;
+
+        if meta.is_some() && accessor.is_some() {
+            return Err(Error::new_spanned(
+                &method.sig,
+                "metamethod cannot be a property accessor",
+            ));
+        }
+
+        let mutable = match method.sig.inputs.first() {
+            Some(FnArg::Receiver(r)) => {
+                if r.reference.is_none() {
+                    return Err(Error::new_spanned(
+                        r,
+                        "method that takes self by value is not supported",
+                    ));
+                }
+
+                r.mutability.is_some()
+            }
+            _ => {
+                if meta.is_some() || accessor.is_some() {
+                    return Err(Error::new_spanned(
+                        &method.sig,
+                        "metamethod and property accessor must take &self or &mut self",
+                    ));
+                }
+
+                continue;
+            }
+        };
+
+        check_signature(&method.sig)?;
+
+        // Arguments of the accessor start after the object and the key.
+        let name = method.sig.ident.clone();
+        let first = if accessor.is_some() { 3 } else { 2 };
+        let (args, values) = expand_arguments(&mut method.sig, first)?;
+        let body = expand_call(&method.sig, quote! { this.#name(#(#values),*) });
+        let (this, function) = if mutable {
+            (
+                quote! { &mut #ty },
+                quote! { locenv::Method::Exclusive(#name) },
+            )
+        } else {
+            (quote! { &#ty }, quote! { locenv::Method::Shared(#name) })
+        };
+
+        let wrapper = quote! {
+            #[allow(unused_variables)]
+            fn #name(this: #this, lua: *mut locenv::api::LuaState) -> Result<std::os::raw::c_int, locenv::LuaError> {
+                let context = locenv::Context::from_lua(lua, locenv::upvalue_index(1))?;
+
+                #args
+                #body
+            }
+        };
+
+        if let Some(accessor) = accessor {
+            let (key, setter) = match accessor {
+                Accessor::Getter(v) => (v.unwrap_or_else(|| name.to_string()), false),
+                Accessor::Setter(v) => (v.unwrap_or_else(|| setter_name(&name)), true),
+            };
+
+            let index = match properties.iter().position(|p| p.name == key) {
+                Some(i) => i,
+                None => {
+                    properties.push(Property {
+                        name: key,
+                        getter: None,
+                        setter: None,
+                    });
+                    properties.len() - 1
+                }
+            };
+
+            let property = &mut properties[index];
+            let slot = if setter {
+                &mut property.setter
+            } else {
+                &mut property.getter
+            };
+
+            if slot.is_some() {
+                return Err(Error::new_spanned(
+                    &method.sig,
+                    format!(
+                        "duplicated {} for property '{}'",
+                        if setter { "setter" } else { "getter" },
+                        property.name
+                    ),
+                ));
+            }
+
+            *slot = Some((wrapper, function));
+            continue;
+        }
+
+        match meta {
+            Some(event) => metamethods.push((wrapper, event, function)),
+            None => methods.push((wrapper, name.to_string(), function)),
+        }
+    }
+
+    let mut wrappers = Vec::new();
+    let mut keys = Vec::new();
+    let mut names = Vec::new();
+
+    for (wrapper, key, function) in methods {
+        wrappers.push(wrapper);
+        keys.push(key);
+        names.push(function);
+    }
+
+    let mut meta_wrappers = Vec::new();
+    let mut events = Vec::new();
+    let mut functions = Vec::new();
+
+    for (wrapper, event, function) in metamethods {
+        meta_wrappers.push(wrapper);
+        events.push(event);
+        functions.push(function);
+    }
+
+    let mut accessor_wrappers = Vec::new();
+    let mut property_entries = Vec::new();
+
+    for property in properties {
+        let name = property.name;
+        let getter = match property.getter {
+            Some((wrapper, f)) => {
+                accessor_wrappers.push(wrapper);
+                quote! { Some(#f) }
+            }
+            None => quote! { None },
+        };
+        let setter = match property.setter {
+            Some((wrapper, f)) => {
+                accessor_wrappers.push(wrapper);
+                quote! { Some(#f) }
+            }
+            None => quote! { None },
+        };
+
+        property_entries.push(quote! {
+            locenv::PropertyEntry { name: #name, getter: #getter, setter: #setter }
+        });
+    }
+
+    let user_values = args.user_values.map(|v| {
+        quote! { const USER_VALUES: std::os::raw::c_int = #v; }
+    });
+
+    Ok(quote! {
+        #input
+
+        impl locenv::Object for #ty {
+            #user_values
+
+            fn methods() -> &'static [locenv::MethodEntry<Self>] {
+                #(#wrappers)*
+
+                &[#(locenv::MethodEntry { name: #keys, function: #names }),*]
+            }
+
+            fn metamethods() -> &'static [locenv::MetamethodEntry<Self>] {
+                #(#meta_wrappers)*
+
+                &[#(locenv::MetamethodEntry { event: #events, function: #functions }),*]
+            }
+
+            fn properties() -> &'static [locenv::PropertyEntry<Self>] {
+                #(#accessor_wrappers)*
+
+                &[#(#property_entries),*]
+            }
+        }
+
+        impl locenv::Return for #ty {
+            fn into_lua(self, lua: *mut locenv::api::LuaState) -> Result<std::os::raw::c_int, locenv::LuaError> {
+                locenv::new_userdata(lua, locenv::upvalue_index(1), self)?;
+                Ok(1)
+            }
+        }
+    })
+}
+
+struct Property {
+    name: String,
+    getter: Option<(TokenStream, TokenStream)>,
+    setter: Option<(TokenStream, TokenStream)>,
+}
+
+enum Accessor {
+    Getter(Option<String>),
+    Setter(Option<String>),
+}
+
+fn take_accessor(attrs: &mut Vec<Attribute>) -> syn::Result<Option<Accessor>> {
+    let mut result = None;
+    let mut i = 0;
+
+    while i < attrs.len() {
+        let setter = if attrs[i].path.is_ident("getter") {
+            false
+        } else if attrs[i].path.is_ident("setter") {
+            true
+        } else {
+            i += 1;
+            continue;
+        };
+
+        let attr = attrs.remove(i);
+
+        if result.is_some() {
+            return Err(Error::new_spanned(
+                attr,
+                "a method can be only one property accessor",
+            ));
+        }
+
+        let name = if attr.tokens.is_empty() {
+            None
+        } else {
+            Some(attr.parse_args_with(Ident::parse_any)?.to_string())
+        };
+
+        result = Some(if setter {
+            Accessor::Setter(name)
+        } else {
+            Accessor::Getter(name)
+        });
+    }
+
+    Ok(result)
+}
+
+fn setter_name(method: &Ident) -> String {
+    let name = method.to_string();
+
+    match name.strip_prefix("set_") {
+        Some(v) if !v.is_empty() => v.into(),
+        _ => name,
+    }
+}
+
+fn metamethod(event: &Ident) -> syn::Result<TokenStream> {
+    let variant = match event.to_string().as_str() {
+        "tostring" => "ToString",
+        "eq" => "Eq",
+        "lt" => "Lt",
+        "le" => "Le",
+        "len" => "Len",
+        "call" => "Call",
+        "concat" => "Concat",
+        "close" => "Close",
+        "add" => "Add",
+        "sub" => "Sub",
+        "mul" => "Mul",
+        "div" => "Div",
+        "mod" => "Mod",
+        "pow" => "Pow",
+        "unm" => "Unm",
+        "idiv" => "IDiv",
+        "band" => "BAnd",
+        "bor" => "BOr",
+        "bxor" => "BXor",
+        "shl" => "Shl",
+        "shr" => "Shr",
+        "bnot" => "BNot",
+        _ => return Err(Error::new_spanned(event, "unknown metamethod")),
+    };
+
+    let variant = Ident::new(variant, event.span());
+
+    Ok(quote! { locenv::Metamethod::#variant })
+}
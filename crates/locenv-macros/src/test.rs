@@ -0,0 +1,113 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{Error, Expr, ExprLit, ItemFn, Lit, LitStr, Path, Stmt, Token};
+
+pub struct Args {
+    module: Path,
+    name: Option<LitStr>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let module = input.parse()?;
+        let mut name = None;
+
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+
+            if key != "name" {
+                return Err(Error::new_spanned(key, "unknown argument"));
+            }
+
+            input.parse::<Token![=]>()?;
+            name = Some(input.parse()?);
+            input.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self { module, name })
+    }
+}
+
+pub fn expand(args: Args, mut item: ItemFn) -> syn::Result<TokenStream> {
+    // Get module name.
+    let module = &args.module;
+    let name = match args.name {
+        Some(v) => v.value(),
+        None => match module.segments.last() {
+            Some(v) if !matches!(v.ident.to_string().as_str(), "crate" | "self" | "super") => {
+                v.ident.to_string()
+            }
+            _ => {
+                return Err(Error::new_spanned(
+                    module,
+                    "the module name is required for this path (e.g. name = \"mymodule\")",
+                ))
+            }
+        },
+    };
+
+    // Generate test body.
+    let test = &item.sig.ident;
+    let attrs = std::mem::take(&mut item.attrs);
+    let require = format!("{0} = require '{0}'", name);
+    let body = match chunk(&item) {
+        Some(chunk) => quote! {
+            if let Err(e) = host.exec(#chunk) {
+                panic!("{}", e);
+            }
+        },
+        None => {
+            let call = match item.sig.inputs.len() {
+                0 => quote! { #test() },
+                1 => quote! { #test(&host) },
+                _ => {
+                    return Err(Error::new_spanned(
+                        &item.sig.inputs,
+                        "the test can only have a parameter to receive &locenv_test::Host",
+                    ))
+                }
+            };
+
+            quote! {
+                #item
+
+                #call
+            }
+        }
+    };
+
+    let output = &item.sig.output;
+
+    Ok(quote! {
+        #(#attrs)*
+        #[test]
+        fn #test() #output {
+            let mut host = locenv_test::Host::new();
+
+            if let Err(e) = host.load(#name, #module::bootstrap) {
+                panic!("cannot load module '{}': {}", #name, e);
+            }
+
+            if let Err(e) = host.exec(#require) {
+                panic!("{}", e);
+            }
+
+            #body
+        }
+    })
+}
+
+/// Gets the Lua chunk if the body of the test is a single string literal.
+fn chunk(item: &ItemFn) -> Option<&LitStr> {
+    if item.block.stmts.len() != 1 {
+        return None;
+    }
+
+    match &item.block.stmts[0] {
+        Stmt::Expr(Expr::Lit(ExprLit {
+            lit: Lit::Str(v), ..
+        })) => Some(v),
+        _ => None,
+    }
+}
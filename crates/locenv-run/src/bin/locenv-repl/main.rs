@@ -0,0 +1,214 @@
+use locenv::LuaError;
+use locenv_run::{module_name, open_module};
+use locenv_test::Host;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: locenv-repl [OPTIONS] <MODULE>
+
+Load a locenv module from its dynamic library (e.g. target/debug/libmymodule.so) into a global variable with the same
+name as the module then start an interactive Lua prompt.
+
+Options:
+  -n, --name <NAME>              Name of the module [default: the name of MODULE without lib prefix]
+  -C, --working-directory <DIR>  Working directory of the module [default: the current directory]
+  -h, --help                     Print this help";
+
+const REPL: &str = include_str!("repl.lua");
+
+struct Args {
+    module: PathBuf,
+    name: Option<String>,
+    working_directory: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("locenv-repl: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("locenv-repl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let name = match &args.name {
+        Some(v) => v.as_str(),
+        None => module_name(&args.module)
+            .ok_or("cannot determine the module name, specify it with --name")?,
+    };
+
+    // The library must be unloaded after the host.
+    let (_library, bootstrap) = open_module(&args.module)?;
+    let mut host = Host::new();
+
+    if let Some(v) = &args.working_directory {
+        host.set_working_directory(v.canonicalize()?);
+    }
+
+    host.load(name, bootstrap)?;
+    host.exec(&format!("_G[{0:?}] = require {0:?}", name))?;
+
+    // Start prompt.
+    let mut editor = DefaultEditor::new()?;
+    let mut source = String::new();
+
+    println!(
+        "Module '{}' is available as a global variable. Press Ctrl-D to exit.",
+        name
+    );
+
+    loop {
+        let prompt = if source.is_empty() { "> " } else { ">> " };
+        let line = match editor.readline(prompt) {
+            Ok(v) => v,
+            Err(ReadlineError::Interrupted) => {
+                source.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !source.is_empty() {
+            source.push('\n');
+        }
+
+        source.push_str(&line);
+
+        // Evaluate.
+        match evaluate(&host, &source) {
+            Ok((status, output)) => match status.as_str() {
+                "incomplete" => continue,
+                "ok" => {
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                }
+                _ => eprintln!("{}", output),
+            },
+            Err(e) => eprintln!("{}", e),
+        }
+
+        editor.add_history_entry(source.as_str())?;
+        source.clear();
+    }
+
+    Ok(())
+}
+
+/// Evaluates `source` from the prompt and returns the status ("ok", "incomplete" or "error") with the text to print.
+fn evaluate(host: &Host, source: &str) -> Result<(String, String), LuaError> {
+    host.eval(&format!("{}\nreturn repl({})", REPL, long_string(source)))
+}
+
+/// Quotes `value` as a Lua long string so it can be embedded in a chunk as-is.
+fn long_string(value: &str) -> String {
+    let mut level = String::new();
+
+    while value.contains(&format!("]{}]", level)) || value.ends_with(&format!("]{}", level)) {
+        level.push('=');
+    }
+
+    // The first newline right after the opening bracket is skipped by Lua.
+    format!("[{0}[\n{1}]{0}]", level, value)
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut module = None;
+    let mut name = None;
+    let mut working_directory = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| args.next().ok_or(format!("{} requires a value", option));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-n" | "--name" => name = Some(value(&arg)?),
+            "-C" | "--working-directory" => working_directory = Some(value(&arg)?.into()),
+            v if v.starts_with('-') => return Err(format!("unknown option {}", v)),
+            _ if module.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => module = Some(arg.into()),
+        }
+    }
+
+    Ok(Some(Args {
+        module: module.ok_or("no module is specified")?,
+        name,
+        working_directory,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_string_level() {
+        assert_eq!(long_string("abc"), "[[\nabc]]");
+        assert_eq!(long_string("a]]b"), "[=[\na]]b]=]");
+        assert_eq!(long_string("a]]b]=]c"), "[==[\na]]b]=]c]==]");
+        assert_eq!(long_string("t[1]"), "[=[\nt[1]]=]");
+    }
+
+    #[test]
+    fn long_string_roundtrip() {
+        let host = Host::new();
+
+        for value in ["abc", "\nline\n", "a]]b", "t[1]", "x]=", "]"] {
+            let result: String = host
+                .eval(&format!("return {}", long_string(value)))
+                .unwrap();
+
+            assert_eq!(result, value);
+        }
+    }
+
+    #[test]
+    fn incomplete_chunk() {
+        let host = Host::new();
+        let status = |source: &str| evaluate(&host, source).unwrap().0;
+
+        assert_eq!(status("function f()"), "incomplete");
+        assert_eq!(status("local t = {"), "incomplete");
+        assert_eq!(status("x = [[abc"), "incomplete");
+        assert_eq!(status("function f()\nend"), "ok");
+        assert_eq!(status("1 +* 2"), "error");
+        assert_eq!(evaluate(&host, "1 + 1").unwrap(), ("ok".into(), "2".into()));
+    }
+
+    #[test]
+    fn raising_metamethod() {
+        let host = Host::new();
+        let (status, output) = evaluate(
+            &host,
+            "setmetatable({}, { __pairs = function() error('bad pairs') end })",
+        )
+        .unwrap();
+
+        assert_eq!(status, "error");
+        assert!(output.contains("bad pairs"), "{}", output);
+
+        let (status, _) =
+            evaluate(&host, "error(setmetatable({}, { __tostring = error }))").unwrap();
+
+        assert_eq!(status, "error");
+    }
+}
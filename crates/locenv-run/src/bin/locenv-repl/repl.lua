@@ -0,0 +1,112 @@
+-- Evaluates a line from the prompt and returns the status ("ok", "incomplete" or "error") with the text to print.
+local function repl(source)
+  local function inspect(value, depth, seen)
+    local t = type(value)
+
+    if t == 'string' then
+      return string.format('%q', value)
+    elseif t == 'userdata' then
+      local mt = debug.getmetatable(value)
+      local name = type(mt) == 'table' and rawget(mt, '__name')
+      local address = string.format('%p', value)
+
+      if type(name) ~= 'string' then
+        return 'userdata: ' .. address
+      elseif rawget(mt, '__tostring') then
+        return name .. ': ' .. address .. ' (' .. tostring(value) .. ')'
+      else
+        return name .. ': ' .. address
+      end
+    elseif t ~= 'table' then
+      return tostring(value)
+    elseif seen[value] or depth > 2 then
+      return tostring(value)
+    end
+
+    -- Format table.
+    local items = {}
+    local length = #value
+
+    seen[value] = true
+
+    for i = 1, length do
+      items[#items + 1] = inspect(value[i], depth + 1, seen)
+    end
+
+    for k, v in pairs(value) do
+      if math.type(k) ~= 'integer' or k < 1 or k > length then
+        local key
+
+        if type(k) == 'string' and k:match('^[%a_][%w_]*$') then
+          key = k
+        else
+          key = '[' .. inspect(k, depth + 1, seen) .. ']'
+        end
+
+        items[#items + 1] = key .. ' = ' .. inspect(v, depth + 1, seen)
+      end
+    end
+
+    seen[value] = nil
+
+    if #items == 0 then
+      return '{}'
+    end
+
+    return '{ ' .. table.concat(items, ', ') .. ' }'
+  end
+
+  -- Compile as an expression first.
+  local chunk = load('return ' .. source, '=stdin')
+
+  if not chunk then
+    local message
+
+    chunk, message = load(source, '=stdin')
+
+    if not chunk then
+      if message:sub(-5) == '<eof>' then
+        return 'incomplete', ''
+      else
+        return 'error', message
+      end
+    end
+  end
+
+  -- Run.
+  local results = table.pack(xpcall(chunk, debug.traceback))
+
+  if not results[1] then
+    local _, message = pcall(tostring, results[2])
+
+    if type(message) ~= 'string' then
+      message = '(error object is not a string)'
+    end
+
+    local i = message:find("\n\t%[C%]: in function 'xpcall'")
+
+    -- Remove the frames of this function from the traceback.
+    if i then
+      message = message:sub(1, i - 1)
+    end
+
+    return 'error', message
+  end
+
+  -- The metamethods of the results may raise an error so format them in protected mode.
+  local ok, output = pcall(function()
+    local values = {}
+
+    for i = 2, results.n do
+      values[#values + 1] = inspect(results[i], 0, {})
+    end
+
+    return table.concat(values, '\t')
+  end)
+
+  if not ok then
+    return 'error', 'cannot display the result: ' .. tostring(output)
+  end
+
+  return 'ok', output
+end
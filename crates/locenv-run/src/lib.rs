@@ -0,0 +1,52 @@
+//! Shared functionalities of `locenv-run` binaries.
+use libloading::Library;
+use locenv_test::Bootstrap;
+use std::error::Error;
+use std::path::Path;
+
+/// Opens the dynamic library of a module at `path` and gets its `bootstrap` function. The returned [`Library`] must
+/// outlive the [`Host`](locenv_test::Host) that loads the module.
+pub fn open_module(path: &Path) -> Result<(Library, Bootstrap), Box<dyn Error>> {
+    let library = unsafe { Library::new(path) }
+        .map_err(|e| format!("cannot load {}: {}", path.display(), e))?;
+    let bootstrap = unsafe { library.get::<Bootstrap>(b"bootstrap\0") }.map_err(|e| {
+        format!(
+            "{} is not a locenv module ({}), did you forget #[loader] or #[module]?",
+            path.display(),
+            e
+        )
+    })?;
+    let bootstrap = *bootstrap;
+
+    Ok((library, bootstrap))
+}
+
+/// Gets a module name from the path of its dynamic library (e.g. `libmymodule.so` to `mymodule`). The `lib` prefix is
+/// kept for a DLL since Rust does not add it on Windows.
+pub fn module_name(path: &Path) -> Option<&str> {
+    let name = path.file_stem()?.to_str()?;
+
+    match path.extension() {
+        Some(v) if v.eq_ignore_ascii_case("dll") => Some(name),
+        _ => Some(name.strip_prefix("lib").unwrap_or(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_name_from_library() {
+        fn name(path: &str) -> Option<&str> {
+            module_name(Path::new(path))
+        }
+
+        assert_eq!(name("target/debug/libmymodule.so"), Some("mymodule"));
+        assert_eq!(name("target/debug/libmymodule.dylib"), Some("mymodule"));
+        assert_eq!(name("target/debug/mymodule.dll"), Some("mymodule"));
+        assert_eq!(name("target/debug/libmymodule.dll"), Some("libmymodule"));
+        assert_eq!(name("mymodule.so"), Some("mymodule"));
+        assert_eq!(name("/"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use locenv_run::{module_name, open_module};
+use locenv_test::Host;
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: locenv-run [OPTIONS] <MODULE> [SCRIPT]
+
+Load a locenv module from its dynamic library (e.g. target/debug/libmymodule.so) then run SCRIPT, which can require
+the module by its name the same as on locenv.
+
+Options:
+  -e <CHUNK>                     Run CHUNK instead of SCRIPT
+  -n, --name <NAME>              Name of the module [default: the name of MODULE without lib prefix]
+  -C, --working-directory <DIR>  Working directory of the module [default: the current directory]
+  -h, --help                     Print this help";
+
+struct Args {
+    module: PathBuf,
+    name: Option<String>,
+    working_directory: Option<PathBuf>,
+    script: Script,
+}
+
+enum Script {
+    File(PathBuf),
+    Chunk(String),
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("locenv-run: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("locenv-run: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let name = match &args.name {
+        Some(v) => v.as_str(),
+        None => module_name(&args.module)
+            .ok_or("cannot determine the module name, specify it with --name")?,
+    };
+
+    // The library must be unloaded after the host.
+    let (_library, bootstrap) = open_module(&args.module)?;
+    let mut host = Host::new();
+
+    if let Some(v) = &args.working_directory {
+        host.set_working_directory(v.canonicalize()?);
+    }
+
+    host.load(name, bootstrap)?;
+
+    match &args.script {
+        Script::File(v) => host.exec_file(v)?,
+        Script::Chunk(v) => host.exec(v)?,
+    }
+
+    Ok(())
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut positionals = Vec::new();
+    let mut name = None;
+    let mut working_directory = None;
+    let mut chunk = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| args.next().ok_or(format!("{} requires a value", option));
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-e" => chunk = Some(value(&arg)?),
+            "-n" | "--name" => name = Some(value(&arg)?),
+            "-C" | "--working-directory" => working_directory = Some(value(&arg)?.into()),
+            v if v.starts_with('-') && v != "-" => return Err(format!("unknown option {}", v)),
+            _ => positionals.push(arg),
+        }
+    }
+
+    let mut positionals = positionals.into_iter();
+    let module = positionals.next().ok_or("no module is specified")?.into();
+    let script = match (chunk, positionals.next()) {
+        (Some(_), Some(_)) => return Err("cannot specify both -e and SCRIPT".into()),
+        (Some(v), None) => Script::Chunk(v),
+        (None, Some(v)) => Script::File(v.into()),
+        (None, None) => return Err("no script is specified".into()),
+    };
+
+    if let Some(v) = positionals.next() {
+        return Err(format!("unexpected argument {}", v));
+    }
+
+    Ok(Some(Args {
+        module,
+        name,
+        working_directory,
+        script,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn parse(args: &[&str]) -> Result<Option<Args>, String> {
+        parse_args(args.iter().map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn chunk() {
+        let args = parse(&["-e", "print(1)", "-n", "foo", "-C", "/tmp", "libfoo.so"])
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(args.module, PathBuf::from("libfoo.so"));
+        assert_eq!(args.name.as_deref(), Some("foo"));
+        assert_eq!(args.working_directory, Some(PathBuf::from("/tmp")));
+        assert!(matches!(args.script, Script::Chunk(v) if v == "print(1)"));
+    }
+
+    #[test]
+    fn script() {
+        let args = parse(&["libfoo.so", "test.lua"]).unwrap().unwrap();
+
+        assert_eq!(args.module, PathBuf::from("libfoo.so"));
+        assert_eq!(args.name, None);
+        assert_eq!(args.working_directory, None);
+        assert!(matches!(args.script, Script::File(v) if v == Path::new("test.lua")));
+
+        // A single dash is a script name.
+        let args = parse(&["libfoo.so", "-"]).unwrap().unwrap();
+
+        assert!(matches!(args.script, Script::File(v) if v == Path::new("-")));
+    }
+
+    #[test]
+    fn trailing_args() {
+        assert_eq!(
+            parse(&["libfoo.so", "test.lua", "bar"]).err().unwrap(),
+            "unexpected argument bar"
+        );
+        assert_eq!(
+            parse(&["-e", "print(1)", "libfoo.so", "test.lua"])
+                .err()
+                .unwrap(),
+            "cannot specify both -e and SCRIPT"
+        );
+    }
+
+    #[test]
+    fn missing_module() {
+        assert_eq!(parse(&[]).err().unwrap(), "no module is specified");
+        assert_eq!(
+            parse(&["-e", "print(1)"]).err().unwrap(),
+            "no module is specified"
+        );
+        assert_eq!(
+            parse(&["libfoo.so"]).err().unwrap(),
+            "no script is specified"
+        );
+    }
+
+    #[test]
+    fn invalid_option() {
+        assert_eq!(parse(&["-x"]).err().unwrap(), "unknown option -x");
+        assert_eq!(
+            parse(&["libfoo.so", "-e"]).err().unwrap(),
+            "-e requires a value"
+        );
+        assert!(parse(&["libfoo.so", "-h"]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,5 @@
+fn main() {
+    let lua = lua_src::Build::new().build(lua_src::Lua54);
+
+    lua.print_cargo_metadata();
+}
@@ -0,0 +1,168 @@
+//! Declarations of Lua C API from the vendored Lua. The signatures are the same as the corresponding field in
+//! [`ApiTable`](locenv::api::ApiTable).
+use locenv::api::{LuaAlloc, LuaContinuation, LuaFunction, LuaReader, LuaReg, LuaState, LuaWriter};
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_double, c_int, c_longlong, c_ulonglong};
+
+pub const LUA_OK: c_int = 0;
+
+unsafe extern "C" {
+    pub safe fn luaL_newstate() -> *mut LuaState;
+    pub fn luaL_openlibs(lua: *mut LuaState);
+    pub fn lua_close(lua: *mut LuaState);
+
+    pub fn lua_pushboolean(lua: *mut LuaState, arg1: c_int);
+    pub safe fn lua_pushcclosure(lua: *mut LuaState, arg1: LuaFunction, arg2: c_int);
+    pub fn lua_pushfstring(lua: *mut LuaState, arg1: *const c_char, ...) -> *const c_char;
+    pub fn lua_pushinteger(lua: *mut LuaState, arg1: c_longlong);
+    pub safe fn lua_pushlightuserdata(lua: *mut LuaState, arg1: *mut u8);
+    pub fn lua_pushlstring(lua: *mut LuaState, arg1: *const c_char, arg2: usize) -> *const c_char;
+    pub safe fn lua_pushnil(lua: *mut LuaState);
+    pub fn lua_pushnumber(lua: *mut LuaState, arg1: c_double);
+    pub fn lua_pushstring(lua: *mut LuaState, arg1: *const c_char) -> *const c_char;
+    pub fn lua_pushthread(lua: *mut LuaState) -> c_int;
+    pub safe fn lua_pushvalue(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_pushvfstring(
+        lua: *mut LuaState,
+        arg1: *const c_char,
+        arg2: *mut c_void,
+    ) -> *const c_char;
+    pub safe fn lua_createtable(lua: *mut LuaState, arg1: c_int, arg2: c_int);
+    pub safe fn lua_newuserdatauv(lua: *mut LuaState, arg1: usize, arg2: c_int) -> *mut u8;
+    pub safe fn lua_settable(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_rawset(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_seti(lua: *mut LuaState, arg1: c_int, arg2: c_longlong);
+    pub fn lua_rawseti(lua: *mut LuaState, arg1: c_int, arg2: c_longlong);
+    pub fn lua_setfield(lua: *mut LuaState, arg1: c_int, arg2: *const c_char);
+    pub fn lua_rawsetp(lua: *mut LuaState, arg1: c_int, arg2: *const c_void);
+    pub safe fn lua_setmetatable(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_setiuservalue(lua: *mut LuaState, arg1: c_int, arg2: c_int) -> c_int;
+    pub fn lua_iscfunction(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_isinteger(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_isnumber(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_isstring(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_isuserdata(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub safe fn lua_type(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_typename(lua: *mut LuaState, arg1: c_int) -> *const c_char;
+    pub safe fn lua_getmetatable(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_toboolean(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_tocfunction(lua: *mut LuaState, arg1: c_int) -> LuaFunction;
+    pub fn lua_tointegerx(lua: *mut LuaState, arg1: c_int, arg2: *mut c_int) -> c_longlong;
+    pub fn lua_tolstring(lua: *mut LuaState, arg1: c_int, arg2: *mut usize) -> *const c_char;
+    pub fn lua_tonumberx(lua: *mut LuaState, arg1: c_int, arg2: *mut c_int) -> c_double;
+    pub fn lua_topointer(lua: *mut LuaState, arg1: c_int) -> *const c_void;
+    pub fn lua_tothread(lua: *mut LuaState, arg1: c_int) -> *mut LuaState;
+    pub safe fn lua_touserdata(lua: *mut LuaState, arg1: c_int) -> *mut u8;
+    pub fn lua_geti(lua: *mut LuaState, arg1: c_int, arg2: c_longlong) -> c_int;
+    pub fn lua_rawgeti(lua: *mut LuaState, arg1: c_int, arg2: c_longlong) -> c_int;
+    pub fn lua_gettable(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_rawget(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_getfield(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn lua_rawgetp(lua: *mut LuaState, arg1: c_int, arg2: *const c_void) -> c_int;
+    pub fn lua_next(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_getiuservalue(lua: *mut LuaState, arg1: c_int, arg2: c_int) -> c_int;
+    pub fn lua_getglobal(lua: *mut LuaState, arg1: *const c_char) -> c_int;
+    pub fn lua_setglobal(lua: *mut LuaState, arg1: *const c_char);
+    pub fn lua_gettop(lua: *mut LuaState) -> c_int;
+    pub safe fn lua_settop(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_callk(
+        lua: *mut LuaState,
+        arg1: c_int,
+        arg2: c_int,
+        arg3: isize,
+        arg4: Option<LuaContinuation>,
+    );
+    pub fn lua_pcallk(
+        lua: *mut LuaState,
+        arg1: c_int,
+        arg2: c_int,
+        arg3: c_int,
+        arg4: isize,
+        arg5: Option<LuaContinuation>,
+    ) -> c_int;
+    pub safe fn lua_error(lua: *mut LuaState) -> c_int;
+    pub fn lua_warning(lua: *mut LuaState, arg1: *const c_char, arg2: c_int);
+    pub fn lua_checkstack(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub safe fn lua_absindex(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn lua_copy(lua: *mut LuaState, arg1: c_int, arg2: c_int);
+    pub fn lua_rotate(lua: *mut LuaState, arg1: c_int, arg2: c_int);
+    pub fn lua_len(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_rawlen(lua: *mut LuaState, arg1: c_int) -> c_ulonglong;
+    pub fn lua_compare(lua: *mut LuaState, arg1: c_int, arg2: c_int, arg3: c_int) -> c_int;
+    pub fn lua_rawequal(lua: *mut LuaState, arg1: c_int, arg2: c_int) -> c_int;
+    pub fn lua_arith(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_concat(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_load(
+        lua: *mut LuaState,
+        arg1: LuaReader,
+        arg2: *mut c_void,
+        arg3: *const c_char,
+        arg4: *const c_char,
+    ) -> c_int;
+    pub fn lua_dump(lua: *mut LuaState, arg1: LuaWriter, arg2: *mut c_void, arg3: c_int) -> c_int;
+    pub fn lua_toclose(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_closeslot(lua: *mut LuaState, arg1: c_int);
+    pub fn lua_stringtonumber(lua: *mut LuaState, arg1: *const c_char) -> usize;
+    pub fn lua_getallocf(lua: *mut LuaState, arg1: *mut *mut c_void) -> LuaAlloc;
+    pub fn lua_gc(lua: *mut LuaState, arg1: c_int, ...) -> c_int;
+    pub fn lua_version(lua: *mut LuaState) -> c_double;
+    pub fn luaL_checkany(lua: *mut LuaState, arg1: c_int);
+    pub fn luaL_checkinteger(lua: *mut LuaState, arg1: c_int) -> c_longlong;
+    pub fn luaL_checklstring(lua: *mut LuaState, arg1: c_int, arg2: *mut usize) -> *const c_char;
+    pub fn luaL_checknumber(lua: *mut LuaState, arg1: c_int) -> c_double;
+    pub fn luaL_checkoption(
+        lua: *mut LuaState,
+        arg1: c_int,
+        arg2: *const c_char,
+        arg3: *const *const c_char,
+    ) -> c_int;
+    pub fn luaL_checkudata(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> *mut u8;
+    pub fn luaL_testudata(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> *mut c_void;
+    pub fn luaL_checktype(lua: *mut LuaState, arg1: c_int, arg2: c_int);
+    pub fn luaL_typeerror(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn luaL_argerror(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn luaL_optinteger(lua: *mut LuaState, arg1: c_int, arg2: c_longlong) -> c_longlong;
+    pub fn luaL_optlstring(
+        lua: *mut LuaState,
+        arg1: c_int,
+        arg2: *const c_char,
+        arg3: *mut usize,
+    ) -> *const c_char;
+    pub fn luaL_optnumber(lua: *mut LuaState, arg1: c_int, arg2: c_double) -> c_double;
+    pub fn luaL_error(lua: *mut LuaState, arg1: *const c_char, ...) -> c_int;
+    pub fn luaL_checkstack(lua: *mut LuaState, arg1: c_int, arg2: *const c_char);
+    pub fn luaL_tolstring(lua: *mut LuaState, arg1: c_int, arg2: *mut usize) -> *const c_char;
+    pub fn luaL_len(lua: *mut LuaState, arg1: c_int) -> c_longlong;
+    pub fn luaL_getsubtable(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn luaL_ref(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn luaL_unref(lua: *mut LuaState, arg1: c_int, arg2: c_int);
+    pub fn luaL_newmetatable(lua: *mut LuaState, arg1: *const c_char) -> c_int;
+    pub fn luaL_setmetatable(lua: *mut LuaState, arg1: *const c_char);
+    pub fn luaL_callmeta(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn luaL_getmetafield(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+    pub fn luaL_loadstring(lua: *mut LuaState, arg1: *const c_char) -> c_int;
+    pub fn luaL_loadfilex(lua: *mut LuaState, arg1: *const c_char, arg2: *const c_char) -> c_int;
+    pub fn luaL_loadbufferx(
+        lua: *mut LuaState,
+        arg1: *const c_char,
+        arg2: usize,
+        arg3: *const c_char,
+        arg4: *const c_char,
+    ) -> c_int;
+    pub fn luaL_setfuncs(lua: *mut LuaState, arg1: *const LuaReg, arg2: c_int);
+    pub fn luaL_where(lua: *mut LuaState, arg1: c_int);
+    pub fn luaL_traceback(
+        lua: *mut LuaState,
+        arg1: *mut LuaState,
+        arg2: *const c_char,
+        arg3: c_int,
+    );
+    pub fn luaL_gsub(
+        lua: *mut LuaState,
+        arg1: *const c_char,
+        arg2: *const c_char,
+        arg3: *const c_char,
+    ) -> *const c_char;
+    pub fn luaL_execresult(lua: *mut LuaState, arg1: c_int) -> c_int;
+    pub fn luaL_fileresult(lua: *mut LuaState, arg1: c_int, arg2: *const c_char) -> c_int;
+}
@@ -0,0 +1,420 @@
+//! An in-process locenv host for testing locenv modules.
+//!
+//! [`Host`] runs a real Lua 5.4 that is compiled together with this crate and provides the same [`ApiTable`] as
+//! locenv, so the module can be tested with `cargo test` without installing it into locenv.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use locenv_test::Host;
+//!
+//! let mut host = Host::new();
+//!
+//! host.load("mymodule", mymodule::bootstrap).unwrap();
+//! host.exec("assert(require('mymodule').greet('John') == 'Hello, John!')").unwrap();
+//! # mod mymodule {
+//! #     pub unsafe extern "C" fn bootstrap(
+//! #         _: *const locenv::api::BootstrapContext,
+//! #         _: *const locenv::api::ApiTable,
+//! #     ) -> std::os::raw::c_int {
+//! #         0
+//! #     }
+//! # }
+//! ```
+use self::ffi::LUA_OK;
+use locenv::api::{ApiTable, BootstrapContext, LuaState};
+use locenv::{FromLua, LuaError, API_TABLE, LUA_REGISTRYINDEX, LUA_TTABLE};
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::sync::Once;
+use tempfile::TempDir;
+
+mod ffi;
+
+/// A signature of `bootstrap` function that generated by #\[loader\] or #\[module\] attribute.
+pub type Bootstrap = unsafe extern "C" fn(*const BootstrapContext, *const ApiTable) -> c_int;
+
+/// A key in the registry to store the modules that was loaded with [`Host::load()`].
+const MODULES: &str = "locenv-test.modules";
+
+/// A Lua VM that act as a locenv to run the modules.
+///
+/// The modules is loaded with [`Host::load()`] and can be required from Lua with their name the same as locenv.
+pub struct Host {
+    lua: *mut LuaState,
+    data: Box<Data>,
+    working_directory: CString,
+}
+
+impl Host {
+    /// Creates a new Lua VM with the standard libraries opened. The configurations of the modules will be stored in a
+    /// temporary directory that will be removed when the host is dropped.
+    pub fn new() -> Self {
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| unsafe { API_TABLE = &API });
+
+        // Create a directory to act as $LOCENV_DATA.
+        let data = Box::new(Data {
+            path: TempDir::new().expect("cannot create a temporary directory"),
+        });
+
+        // Create Lua.
+        let lua = ffi::luaL_newstate();
+
+        if lua.is_null() {
+            panic!("not enough memory to create a Lua state");
+        }
+
+        unsafe { ffi::luaL_openlibs(lua) };
+
+        // Install a searcher for the modules.
+        locenv::create_table(lua, 0, 0);
+        locenv::push_value(lua, -1);
+        locenv::set_field(lua, LUA_REGISTRYINDEX, MODULES);
+        locenv::push_fn(lua, search_module, 1);
+
+        unsafe { ffi::lua_getglobal(lua, c"package".as_ptr()) };
+        locenv::get_field(lua, -1, "searchers");
+
+        let count = unsafe { ffi::lua_rawlen(lua, -1) };
+
+        locenv::push_value(lua, -3);
+        unsafe { ffi::lua_rawseti(lua, -2, (count + 1) as _) };
+        locenv::pop(lua, 3);
+
+        // Use the current directory as a working directory.
+        let working_directory = std::env::current_dir().expect("cannot get the current directory");
+        let working_directory = CString::new(working_directory.to_str().unwrap()).unwrap();
+
+        Self {
+            lua,
+            data,
+            working_directory,
+        }
+    }
+
+    /// Gets the underlying Lua state.
+    pub fn lua(&self) -> *mut LuaState {
+        self.lua
+    }
+
+    /// Gets a path to the directory that act as `$LOCENV_DATA`.
+    pub fn data_directory(&self) -> &Path {
+        self.data.path.path()
+    }
+
+    /// Gets a path that will be returned from `Context::working_directory()`.
+    pub fn working_directory(&self) -> &Path {
+        Path::new(self.working_directory.to_str().unwrap())
+    }
+
+    /// Sets a path that will be returned from `Context::working_directory()` for the modules that loaded after this.
+    pub fn set_working_directory<P: AsRef<Path>>(&mut self, path: P) {
+        self.working_directory = CString::new(path.as_ref().to_str().unwrap()).unwrap();
+    }
+
+    /// Loads a module by invoking its `bootstrap` function the same as locenv. The module can be required from Lua
+    /// with `name` after this.
+    pub fn load(&mut self, name: &str, bootstrap: Bootstrap) -> Result<(), LuaError> {
+        let name = CString::new(name).map_err(|_| LuaError::from("module name contains NUL"))?;
+        let context = BootstrapContext {
+            revision: 0,
+            name: name.as_ptr(),
+            locenv: self.data.as_ref() as *const Data as *const c_void,
+            lua: self.lua,
+            working_directory: self.working_directory.as_ptr(),
+        };
+
+        // Bootstrap the module.
+        let lua = self.lua;
+        let top = unsafe { ffi::lua_gettop(lua) };
+        let count = unsafe { bootstrap(&context, &API) };
+
+        if count != 2 {
+            let message = if count == 1 {
+                locenv::to_string(lua, -1).unwrap_or_default()
+            } else {
+                format!("bootstrap returned {} values", count)
+            };
+
+            ffi::lua_settop(lua, top);
+
+            return Err(LuaError::Message(message));
+        }
+
+        // Register the loader.
+        locenv::get_field(lua, LUA_REGISTRYINDEX, MODULES);
+        locenv::create_table(lua, 2, 0);
+        locenv::push_value(lua, -4);
+        unsafe { ffi::lua_rawseti(lua, -2, 1) };
+        locenv::push_value(lua, -3);
+        unsafe { ffi::lua_rawseti(lua, -2, 2) };
+        unsafe { ffi::lua_setfield(lua, -2, name.as_ptr()) };
+        locenv::pop(lua, 3);
+
+        Ok(())
+    }
+
+    /// Runs a Lua chunk. The returned error contains the message and the traceback if the chunk raised an error.
+    pub fn exec(&self, chunk: &str) -> Result<(), LuaError> {
+        self.load_chunk(chunk)?;
+        self.call(0)?;
+        Ok(())
+    }
+
+    /// Runs a Lua file. The returned error contains the message and the traceback if the file raised an error.
+    pub fn exec_file<P: AsRef<Path>>(&self, path: P) -> Result<(), LuaError> {
+        self.load_file(path.as_ref())?;
+        self.call(0)?;
+        Ok(())
+    }
+
+    /// Runs a Lua chunk and converts its return values to `T`.
+    pub fn eval<T: FromLua>(&self, chunk: &str) -> Result<T, LuaError> {
+        let lua = self.lua;
+
+        self.load_chunk(chunk)?;
+
+        let top = self.call(T::SLOTS)?;
+        let result = T::from_lua(lua, top + 1);
+
+        ffi::lua_settop(lua, top);
+
+        result
+    }
+
+    fn load_chunk(&self, chunk: &str) -> Result<(), LuaError> {
+        let status = unsafe {
+            ffi::luaL_loadbufferx(
+                self.lua,
+                chunk.as_ptr() as *const c_char,
+                chunk.len(),
+                c"=chunk".as_ptr(),
+                c"t".as_ptr(),
+            )
+        };
+
+        self.check_load(status)
+    }
+
+    fn load_file(&self, path: &Path) -> Result<(), LuaError> {
+        let path = path
+            .to_str()
+            .and_then(|v| CString::new(v).ok())
+            .ok_or_else(|| LuaError::from("invalid path"))?;
+        let status = unsafe { ffi::luaL_loadfilex(self.lua, path.as_ptr(), c"t".as_ptr()) };
+
+        self.check_load(status)
+    }
+
+    fn check_load(&self, status: c_int) -> Result<(), LuaError> {
+        if status != LUA_OK {
+            let message = locenv::to_string(self.lua, -1).unwrap_or_default();
+            locenv::pop(self.lua, 1);
+            return Err(LuaError::Message(message));
+        }
+
+        Ok(())
+    }
+
+    /// Calls the function on the top of the stack and returns the top of the stack before the function.
+    fn call(&self, results: c_int) -> Result<c_int, LuaError> {
+        let lua = self.lua;
+        let top = unsafe { ffi::lua_gettop(lua) } - 1;
+
+        locenv::push_fn(lua, traceback, 0);
+        locenv::insert(lua, -2);
+
+        let status = unsafe { ffi::lua_pcallk(lua, 0, results, top + 1, 0, None) };
+
+        locenv::remove(lua, top + 1);
+
+        if status != LUA_OK {
+            let message = locenv::to_string(lua, -1).unwrap_or_default();
+            ffi::lua_settop(lua, top);
+            return Err(LuaError::Message(message));
+        }
+
+        Ok(top)
+    }
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        unsafe { ffi::lua_close(self.lua) };
+    }
+}
+
+/// A data that pointed by `BootstrapContext::locenv`.
+struct Data {
+    path: TempDir,
+}
+
+impl Data {
+    fn configurations_path(&self, module: &str) -> PathBuf {
+        self.path.path().join("config").join(module)
+    }
+}
+
+extern "C" fn search_module(lua: *mut LuaState) -> c_int {
+    let name = locenv::to_string(lua, 1).unwrap_or_default();
+
+    // Lookup the module.
+    if locenv::get_field(lua, locenv::upvalue_index(1), &name) != LUA_TTABLE {
+        locenv::push_str(lua, &format!("no module '{}' in the host", name));
+        return 1;
+    }
+
+    // Return the loader and its data.
+    unsafe { ffi::lua_rawgeti(lua, -1, 1) };
+    unsafe { ffi::lua_rawgeti(lua, -2, 2) };
+
+    2
+}
+
+extern "C" fn traceback(lua: *mut LuaState) -> c_int {
+    let message = unsafe { ffi::luaL_tolstring(lua, 1, null_mut()) };
+
+    unsafe { ffi::luaL_traceback(lua, lua, message, 1) };
+
+    1
+}
+
+unsafe extern "C" fn module_configurations_path(
+    locenv: *const c_void,
+    name: *const c_char,
+    buffer: *mut c_char,
+    size: u32,
+) -> u32 {
+    let data = &*(locenv as *const Data);
+    let name = CStr::from_ptr(name).to_str().unwrap();
+    let path = data.configurations_path(name);
+    let path = path.to_str().unwrap().as_bytes();
+    let required = (path.len() + 1) as u32;
+
+    if required <= size {
+        buffer.copy_from_nonoverlapping(path.as_ptr() as *const c_char, path.len());
+        *buffer.add(path.len()) = 0;
+    }
+
+    required
+}
+
+static API: ApiTable = ApiTable {
+    revision: 0,
+    lua_pushboolean: ffi::lua_pushboolean,
+    lua_pushcclosure: ffi::lua_pushcclosure,
+    lua_pushfstring: ffi::lua_pushfstring,
+    lua_pushinteger: ffi::lua_pushinteger,
+    lua_pushlightuserdata: ffi::lua_pushlightuserdata,
+    lua_pushlstring: ffi::lua_pushlstring,
+    lua_pushnil: ffi::lua_pushnil,
+    lua_pushnumber: ffi::lua_pushnumber,
+    lua_pushstring: ffi::lua_pushstring,
+    lua_pushthread: ffi::lua_pushthread,
+    lua_pushvalue: ffi::lua_pushvalue,
+    lua_pushvfstring: ffi::lua_pushvfstring,
+    lua_createtable: ffi::lua_createtable,
+    lua_newuserdatauv: ffi::lua_newuserdatauv,
+    lua_settable: ffi::lua_settable,
+    lua_rawset: ffi::lua_rawset,
+    lua_seti: ffi::lua_seti,
+    lua_rawseti: ffi::lua_rawseti,
+    lua_setfield: ffi::lua_setfield,
+    lua_rawsetp: ffi::lua_rawsetp,
+    lua_setmetatable: ffi::lua_setmetatable,
+    lua_setiuservalue: ffi::lua_setiuservalue,
+    lua_iscfunction: ffi::lua_iscfunction,
+    lua_isinteger: ffi::lua_isinteger,
+    lua_isnumber: ffi::lua_isnumber,
+    lua_isstring: ffi::lua_isstring,
+    lua_isuserdata: ffi::lua_isuserdata,
+    lua_type: ffi::lua_type,
+    lua_typename: ffi::lua_typename,
+    lua_getmetatable: ffi::lua_getmetatable,
+    lua_toboolean: ffi::lua_toboolean,
+    lua_tocfunction: ffi::lua_tocfunction,
+    lua_tointegerx: ffi::lua_tointegerx,
+    lua_tolstring: ffi::lua_tolstring,
+    lua_tonumberx: ffi::lua_tonumberx,
+    lua_topointer: ffi::lua_topointer,
+    lua_tothread: ffi::lua_tothread,
+    lua_touserdata: ffi::lua_touserdata,
+    lua_geti: ffi::lua_geti,
+    lua_rawgeti: ffi::lua_rawgeti,
+    lua_gettable: ffi::lua_gettable,
+    lua_rawget: ffi::lua_rawget,
+    lua_getfield: ffi::lua_getfield,
+    lua_rawgetp: ffi::lua_rawgetp,
+    lua_next: ffi::lua_next,
+    lua_getiuservalue: ffi::lua_getiuservalue,
+    lua_getglobal: ffi::lua_getglobal,
+    lua_setglobal: ffi::lua_setglobal,
+    lua_gettop: ffi::lua_gettop,
+    lua_settop: ffi::lua_settop,
+    lua_callk: ffi::lua_callk,
+    lua_pcallk: ffi::lua_pcallk,
+    lua_error: ffi::lua_error,
+    lua_warning: ffi::lua_warning,
+    lua_checkstack: ffi::lua_checkstack,
+    lua_absindex: ffi::lua_absindex,
+    lua_copy: ffi::lua_copy,
+    lua_rotate: ffi::lua_rotate,
+    lua_len: ffi::lua_len,
+    lua_rawlen: ffi::lua_rawlen,
+    lua_compare: ffi::lua_compare,
+    lua_rawequal: ffi::lua_rawequal,
+    lua_arith: ffi::lua_arith,
+    lua_concat: ffi::lua_concat,
+    lua_load: ffi::lua_load,
+    lua_dump: ffi::lua_dump,
+    lua_toclose: ffi::lua_toclose,
+    lua_closeslot: ffi::lua_closeslot,
+    lua_stringtonumber: ffi::lua_stringtonumber,
+    lua_getallocf: ffi::lua_getallocf,
+    lua_gc: ffi::lua_gc,
+    lua_version: ffi::lua_version,
+    aux_checkany: ffi::luaL_checkany,
+    aux_checkinteger: ffi::luaL_checkinteger,
+    aux_checklstring: ffi::luaL_checklstring,
+    aux_checknumber: ffi::luaL_checknumber,
+    aux_checkoption: ffi::luaL_checkoption,
+    aux_checkudata: ffi::luaL_checkudata,
+    aux_testudata: ffi::luaL_testudata,
+    aux_checktype: ffi::luaL_checktype,
+    aux_typeerror: ffi::luaL_typeerror,
+    aux_argerror: ffi::luaL_argerror,
+    aux_optinteger: ffi::luaL_optinteger,
+    aux_optlstring: ffi::luaL_optlstring,
+    aux_optnumber: ffi::luaL_optnumber,
+    aux_error: ffi::luaL_error,
+    aux_checkstack: ffi::luaL_checkstack,
+    aux_tolstring: ffi::luaL_tolstring,
+    aux_len: ffi::luaL_len,
+    aux_getsubtable: ffi::luaL_getsubtable,
+    aux_ref: ffi::luaL_ref,
+    aux_unref: ffi::luaL_unref,
+    aux_newmetatable: ffi::luaL_newmetatable,
+    aux_setmetatable: ffi::luaL_setmetatable,
+    aux_callmeta: ffi::luaL_callmeta,
+    aux_getmetafield: ffi::luaL_getmetafield,
+    aux_loadstring: ffi::luaL_loadstring,
+    aux_loadfilex: ffi::luaL_loadfilex,
+    aux_loadbufferx: ffi::luaL_loadbufferx,
+    aux_setfuncs: ffi::luaL_setfuncs,
+    aux_where: ffi::luaL_where,
+    aux_traceback: ffi::luaL_traceback,
+    aux_gsub: ffi::luaL_gsub,
+    aux_execresult: ffi::luaL_execresult,
+    aux_fileresult: ffi::luaL_fileresult,
+    module_configurations_path,
+};
@@ -0,0 +1,12 @@
+local name = ...
+local helpers = {}
+
+function helpers.name()
+  return name
+end
+
+function helpers.shout(s)
+  return require(name:gsub('helpers$', 'util.strings')).upper(s) .. '!'
+end
+
+return helpers
@@ -0,0 +1,3 @@
+return {
+  upper = string.upper,
+}
@@ -0,0 +1,1218 @@
+use locenv::{LuaError, Object};
+use locenv_macros::{locenv_test, module};
+use locenv_test::Host;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static DROPPED_VECTORS: AtomicUsize = AtomicUsize::new(0);
+
+#[module(state = Stats)]
+mod testing {
+    use locenv::api::LuaState;
+    use locenv::{
+        check, check_userdata, dump_function, dump_function_to, error, get_user_value, load_bytes,
+        load_file, load_reader, load_str, new_userdata, push_value, set_user_value, test_userdata,
+        upvalue_index, Context, FunctionRef, LoadMode, LuaError, LuaValue, Method, MethodEntry,
+        Object, Return, Table, TableRef, UserData, LUA_TFUNCTION,
+    };
+    use locenv_macros::{embed_lua, function, object};
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+
+    pub const VERSION: &str = "1.0.0";
+
+    embed_lua!("tests/lua/**/*.lua");
+
+    #[derive(Default)]
+    pub struct Stats {
+        visits: i64,
+    }
+
+    #[function]
+    fn greet(name: String) -> String {
+        format!("Hello, {}!", name)
+    }
+
+    #[function]
+    fn add(a: i64, b: Option<i64>) -> i64 {
+        a + b.unwrap_or(1)
+    }
+
+    #[function]
+    fn fail(message: String) -> Result<(), LuaError> {
+        Err(error!("failed: {}", message))
+    }
+
+    #[function]
+    fn explode() {
+        panic!("boom");
+    }
+
+    #[function]
+    fn module_name(context: &Context) -> String {
+        context.module_name().into()
+    }
+
+    #[function]
+    fn working_directory(context: &Context) -> String {
+        context.working_directory().to_str().unwrap().into()
+    }
+
+    #[function]
+    fn configurations_path(context: &Context) -> String {
+        context.configurations_path().to_str().unwrap().into()
+    }
+
+    #[function]
+    fn count(map: HashMap<String, i64>) -> usize {
+        map.len()
+    }
+
+    #[function]
+    fn invert(map: HashMap<String, i64>) -> HashMap<i64, String> {
+        map.into_iter().map(|(k, v)| (v, k)).collect()
+    }
+
+    #[function]
+    fn total(values: Vec<i64>) -> i64 {
+        values.into_iter().sum()
+    }
+
+    #[function]
+    fn to_u8(value: u8) -> u8 {
+        value
+    }
+
+    #[function]
+    fn to_u32(value: u32) -> u32 {
+        value
+    }
+
+    #[function]
+    fn max_u64() -> u64 {
+        u64::MAX
+    }
+
+    #[function]
+    fn new_counter(value: i64) -> Counter {
+        Counter { value }
+    }
+
+    #[function]
+    fn find_counter(value: Option<i64>) -> Result<Option<Counter>, LuaError> {
+        Ok(value.map(|value| Counter { value }))
+    }
+
+    #[function]
+    fn new_counters(values: Vec<i64>) -> Vec<Counter> {
+        values.into_iter().map(|value| Counter { value }).collect()
+    }
+
+    #[function]
+    fn counter_pair(value: i64) -> (Counter, i64) {
+        (Counter { value }, value * 2)
+    }
+
+    #[function]
+    fn counter_value(lua: *mut LuaState) -> Result<i64, LuaError> {
+        Ok(check_userdata::<Counter>(lua, 1)?.borrow().value)
+    }
+
+    #[function]
+    fn is_counter(lua: *mut LuaState) -> bool {
+        test_userdata::<Counter>(lua, 1).is_some()
+    }
+
+    pub struct Counter {
+        value: i64,
+    }
+
+    impl UserData for Counter {
+        fn type_name() -> &'static str {
+            "Counter"
+        }
+    }
+
+    #[object]
+    impl Counter {
+        fn increment(&mut self, by: Option<i64>) -> i64 {
+            self.value += by.unwrap_or(1);
+            self.value
+        }
+
+        fn update(&mut self, lua: *mut LuaState, f: FunctionRef) -> Result<i64, LuaError> {
+            self.value = f.call(lua, self.value)?;
+            Ok(self.value)
+        }
+
+        fn lookup(&mut self, t: Table, key: String) -> Result<LuaValue, LuaError> {
+            t.get(key)
+        }
+
+        fn store(&mut self, t: Table, key: String, value: i64) -> Result<(), LuaError> {
+            t.set(key, value)
+        }
+
+        fn measure(&mut self, t: Table) -> Result<i64, LuaError> {
+            t.len()
+        }
+
+        fn inspect(&self, lua: *mut LuaState, f: FunctionRef) -> Result<(), LuaError> {
+            f.call(lua, self.value)
+        }
+
+        #[getter]
+        fn value(&self) -> i64 {
+            self.value
+        }
+
+        #[setter]
+        fn set_value(&mut self, value: i64) {
+            self.value = value;
+        }
+
+        #[getter]
+        fn negative(&self) -> bool {
+            self.value < 0
+        }
+
+        #[meta(tostring)]
+        fn describe(&self) -> String {
+            format!("Counter({})", self.value)
+        }
+
+        #[meta(len)]
+        fn len(&self) -> i64 {
+            self.value
+        }
+
+        #[meta(add)]
+        fn add(&self, other: i64) -> i64 {
+            self.value + other
+        }
+
+        #[meta(mod)]
+        fn modulo(&self, other: i64) -> i64 {
+            self.value % other
+        }
+    }
+
+    #[function]
+    fn new_vector(x: f64, y: f64) -> Vector {
+        Vector { x, y }
+    }
+
+    #[repr(align(32))]
+    pub struct Vector {
+        x: f64,
+        y: f64,
+    }
+
+    impl Drop for Vector {
+        fn drop(&mut self) {
+            super::DROPPED_VECTORS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    impl UserData for Vector {
+        const INLINE: bool = true;
+
+        fn type_name() -> &'static str {
+            "Vector"
+        }
+    }
+
+    #[object]
+    impl Vector {
+        fn aligned(&self) -> bool {
+            (self as *const Self).align_offset(32) == 0
+        }
+
+        fn scale(&mut self, by: f64) {
+            self.x *= by;
+            self.y *= by;
+        }
+
+        #[getter]
+        fn x(&self) -> f64 {
+            self.x
+        }
+
+        #[getter]
+        fn y(&self) -> f64 {
+            self.y
+        }
+    }
+
+    #[function]
+    fn new_buffer(capacity: i64) -> Buffer {
+        Buffer(Vec::with_capacity(capacity as _))
+    }
+
+    pub struct Buffer(Vec<u8>);
+
+    impl UserData for Buffer {
+        fn type_name() -> &'static str {
+            "Buffer"
+        }
+
+        fn external_size(&self) -> usize {
+            self.0.capacity()
+        }
+    }
+
+    #[object(user_values = 2)]
+    impl Buffer {
+        fn reserve(&mut self, additional: i64) {
+            self.0.reserve(additional as _);
+        }
+    }
+
+    #[function]
+    fn new_emitter() -> Emitter {
+        Emitter
+    }
+
+    pub struct Emitter;
+
+    impl UserData for Emitter {
+        fn type_name() -> &'static str {
+            "Emitter"
+        }
+    }
+
+    impl Object for Emitter {
+        const USER_VALUES: c_int = 1;
+
+        fn methods() -> &'static [MethodEntry<Self>] {
+            &[
+                MethodEntry {
+                    name: "on",
+                    function: Method::Shared(Self::on),
+                },
+                MethodEntry {
+                    name: "emit",
+                    function: Method::Shared(Self::emit),
+                },
+            ]
+        }
+    }
+
+    impl Return for Emitter {
+        fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+            new_userdata(lua, upvalue_index(1), self)?;
+            Ok(1)
+        }
+    }
+
+    impl Emitter {
+        fn on(&self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+            push_value(lua, 2);
+
+            if !set_user_value(lua, 1, 1) {
+                return Err(error!("no slot for the listener"));
+            }
+
+            Ok(0)
+        }
+
+        fn emit(&self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+            if get_user_value(lua, 1, 1) != LUA_TFUNCTION {
+                return Err(error!("no listener"));
+            }
+
+            let value: i64 = check(lua, 2)?;
+            let listener = FunctionRef::from_index(lua, -1)?;
+
+            listener.call::<_, ()>(lua, value)?;
+
+            Ok(0)
+        }
+    }
+
+    #[function]
+    fn apply(lua: *mut LuaState, f: FunctionRef, value: i64) -> Result<(i64, String), LuaError> {
+        f.call(lua, (value, "foo"))
+    }
+
+    #[function]
+    fn try_call(lua: *mut LuaState, f: FunctionRef) -> String {
+        match f.call::<_, ()>(lua, ()) {
+            Ok(_) => "ok".into(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[function]
+    fn sum(t: Table) -> Result<i64, LuaError> {
+        t.sequence::<i64>().sum()
+    }
+
+    #[function]
+    fn keys(lua: *mut LuaState, t: Table) -> Result<Vec<String>, LuaError> {
+        let top = locenv::abs_index(lua, -1);
+        let mut keys = Vec::new();
+
+        for pair in t.pairs::<String, LuaValue>() {
+            keys.push(pair?.0);
+        }
+
+        // Stop in the middle.
+        if let Some(pair) = t.pairs::<LuaValue, LuaValue>().next() {
+            pair?;
+        }
+
+        if locenv::abs_index(lua, -1) != top {
+            return Err(error!("unbalanced stack"));
+        }
+
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    #[function]
+    fn build(lua: *mut LuaState, source: Table) -> Result<Table, LuaError> {
+        let t = Table::new(lua, 2, 2);
+
+        t.set("a", source.get::<_, i64>("a")?)?;
+        t.set("b", source.raw_get::<_, Option<i64>>("a")?)?;
+        t.raw_set(2, "b");
+        t.raw_set_index(1, "x");
+        t.set("len", t.len()?)?;
+        t.set("raw_len", source.raw_len())?;
+        t.set("first", t.raw_get_index::<String>(1)?)?;
+
+        Ok(t)
+    }
+
+    #[function]
+    fn eval(lua: *mut LuaState, code: String) -> Result<LuaValue, LuaError> {
+        load_str(lua, &code, "=eval")?.call(lua, ())
+    }
+
+    #[function]
+    fn eval_binary(lua: *mut LuaState, code: String) -> Result<LuaValue, LuaError> {
+        load_bytes(lua, code.as_bytes(), "=eval", LoadMode::Binary)?.call(lua, ())
+    }
+
+    #[function]
+    fn eval_reader(lua: *mut LuaState, code: String) -> Result<LuaValue, LuaError> {
+        load_reader(lua, code.as_bytes(), "=reader", LoadMode::Text)?.call(lua, ())
+    }
+
+    #[function]
+    fn eval_broken_reader(lua: *mut LuaState) -> Result<(), LuaError> {
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("broken"))
+            }
+        }
+
+        load_reader(lua, Broken, "=broken", LoadMode::Text)?;
+
+        Ok(())
+    }
+
+    #[function]
+    fn run_file(lua: *mut LuaState, context: &Context, path: String) -> Result<LuaValue, LuaError> {
+        load_file(lua, context, path, LoadMode::Text)?.call(lua, ())
+    }
+
+    #[function]
+    fn roundtrip(
+        lua: *mut LuaState,
+        _function: LuaValue,
+        strip: bool,
+    ) -> Result<LuaValue, LuaError> {
+        let chunk = if strip {
+            dump_function(lua, 1, true)?
+        } else {
+            let mut chunk = std::io::Cursor::new(Vec::new());
+            dump_function_to(lua, 1, false, &mut chunk)?;
+            chunk.into_inner()
+        };
+
+        load_bytes(lua, &chunk, "=roundtrip", LoadMode::Binary)?.call(lua, ())
+    }
+
+    #[module]
+    mod fs {
+        use locenv::{Context, LuaError};
+        use locenv_macros::function;
+
+        pub const SEPARATOR: &str = "/";
+
+        #[function]
+        fn join(a: String, b: String) -> String {
+            format!("{}/{}", a, b)
+        }
+
+        #[function]
+        fn owner(context: &Context) -> String {
+            context.module_name().into()
+        }
+
+        #[function]
+        fn visits(context: &Context) -> Result<i64, LuaError> {
+            Ok(context.state::<super::Stats>()?.visits)
+        }
+
+        #[module]
+        mod path {
+            use locenv_macros::function;
+
+            #[function]
+            fn extension(path: String) -> Option<String> {
+                path.rsplit_once('.').map(|v| v.1.into())
+            }
+        }
+    }
+
+    #[function]
+    fn visit(#[state] stats: &mut Stats) -> i64 {
+        stats.visits += 1;
+        stats.visits
+    }
+
+    #[function]
+    fn invalid_state(context: &Context) -> Result<(), LuaError> {
+        context.state::<String>()?;
+        Ok(())
+    }
+
+    #[function]
+    fn hold(table: TableRef, value: LuaValue) -> Holder {
+        Holder { table, value }
+    }
+
+    pub struct Holder {
+        table: TableRef,
+        value: LuaValue,
+    }
+
+    impl UserData for Holder {
+        fn type_name() -> &'static str {
+            "Holder"
+        }
+    }
+
+    #[object]
+    impl Holder {
+        #[getter]
+        fn table(&self) -> TableRef {
+            self.table.clone()
+        }
+
+        #[getter]
+        fn value(&self) -> LuaValue {
+            self.value.clone()
+        }
+    }
+}
+
+fn host() -> Host {
+    let mut host = Host::new();
+
+    host.load("testing", testing::bootstrap).unwrap();
+    host.exec("testing = require 'testing'").unwrap();
+    host
+}
+
+fn message(error: LuaError) -> String {
+    match error {
+        LuaError::Message(v) => v,
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn function() {
+    let host = host();
+
+    assert_eq!(
+        host.eval::<String>("return testing.greet('John')").unwrap(),
+        "Hello, John!"
+    );
+    assert_eq!(host.eval::<i64>("return testing.add(1, 2)").unwrap(), 3);
+    assert_eq!(host.eval::<i64>("return testing.add(1)").unwrap(), 2);
+    assert_eq!(
+        host.eval::<String>("return testing.VERSION").unwrap(),
+        "1.0.0"
+    );
+}
+
+#[test]
+fn argument_error() {
+    let host = host();
+    let error = message(host.exec("testing.add('a')").unwrap_err());
+
+    assert!(error.contains("bad argument #1 to 'add'"), "{}", error);
+}
+
+#[test]
+fn runtime_error() {
+    let host = host();
+    let error = message(host.exec("testing.fail('foo')").unwrap_err());
+
+    assert!(error.contains("failed: foo"), "{}", error);
+    assert!(error.contains("stack traceback:"), "{}", error);
+}
+
+#[test]
+fn panic() {
+    let host = host();
+    let error = message(host.exec("testing.explode()").unwrap_err());
+
+    assert!(
+        error.contains("module 'testing' panicked: boom"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn context() {
+    let host = host();
+    let config = host.data_directory().join("config").join("testing");
+
+    assert_eq!(
+        host.eval::<String>("return testing.module_name()").unwrap(),
+        "testing"
+    );
+    assert_eq!(
+        host.eval::<String>("return testing.working_directory()")
+            .unwrap(),
+        host.working_directory().to_str().unwrap()
+    );
+    assert_eq!(
+        host.eval::<String>("return testing.configurations_path()")
+            .unwrap(),
+        config.to_str().unwrap()
+    );
+}
+
+#[test]
+fn object() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(5)
+
+        assert(counter:increment() == 6)
+        assert(counter:increment(4) == 10)
+        assert(tostring(counter) == 'Counter(10)')
+        assert(#counter == 10)
+        assert(counter + 2 == 12)
+        assert(counter % 3 == 1)
+        assert(testing.Counter.increment == counter.increment)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn conversion() {
+    let host = host();
+
+    host.exec(
+        r#"
+        assert(testing.count({ a = 1, b = 2 }) == 2)
+        assert(testing.count({}) == 0)
+        assert(testing.invert({ a = 1, b = 2 })[2] == 'b')
+        assert(testing.total({ 1, 2, 3 }) == 6)
+        assert(testing.total({}) == 0)
+        assert(testing.to_u8(255) == 255)
+        assert(testing.to_u8(3.0) == 3)
+        assert(testing.to_u32(4294967295) == 4294967295)
+        assert(math.type(testing.max_u64()) == 'float')
+        assert(testing.max_u64() == 2^64)
+
+        local function fails(pattern, f, ...)
+          local ok, e = pcall(f, ...)
+
+          assert(not ok)
+          assert(e:find(pattern, 1, true), e)
+        end
+
+        fails("duplicate key '1' (string expected)", testing.count, { [1] = 1, ['1'] = 2, a = 3 })
+        fails('invalid key (string expected)', testing.count, { [true] = 1 })
+        fails('invalid value (integer expected)', testing.count, { a = 'x' })
+        fails('invalid element at index 2 (integer expected)', testing.total, { 1, 'x' })
+        fails('integer out of range', testing.to_u8, 256)
+        fails('integer out of range', testing.to_u8, -1)
+        fails('integer out of range', testing.to_u32, -1)
+        fails('integer expected', testing.to_u8, 1.5)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn object_container() {
+    let host = host();
+
+    host.exec(
+        r#"
+        assert(testing.find_counter(3).value == 3)
+        assert(testing.find_counter() == nil)
+
+        local counters = testing.new_counters({ 1, 2, 3 })
+
+        assert(#counters == 3)
+        assert(counters[3].value == 3)
+
+        local counter, double = testing.counter_pair(4)
+
+        assert(counter.value == 4)
+        assert(double == 8)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn property() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(5)
+
+        assert(counter.value == 5)
+        counter.value = -1
+        assert(counter.value == -1)
+        assert(counter.negative)
+        assert(counter.unknown == nil)
+        assert(counter['\xff'] == nil)
+        "#,
+    )
+    .unwrap();
+
+    let error = message(
+        host.exec("testing.new_counter(1).negative = true")
+            .unwrap_err(),
+    );
+
+    assert!(
+        error.contains("property 'negative' is read-only"),
+        "{}",
+        error
+    );
+
+    let error = message(host.exec("testing.new_counter(1).foo = 1").unwrap_err());
+
+    assert!(error.contains("unknown property 'foo'"), "{}", error);
+
+    let error = message(
+        host.exec("testing.new_counter(1)['\\xff'] = 1")
+            .unwrap_err(),
+    );
+
+    assert!(error.contains("unknown property '\u{fffd}'"), "{}", error);
+}
+
+#[test]
+fn unknown_module() {
+    let host = host();
+    let error = message(host.exec("require 'foo'").unwrap_err());
+
+    assert!(error.contains("no module 'foo' in the host"), "{}", error);
+}
+
+#[locenv_test(testing)]
+fn lua_chunk() {
+    r#"
+    local counter = testing.new_counter(1)
+
+    assert(testing.greet('John') == 'Hello, John!')
+    assert(counter:increment() == 2)
+    "#
+}
+
+#[locenv_test(testing)]
+#[should_panic(expected = "stack traceback:")]
+fn lua_assert() {
+    "assert(testing.add(1) == 3)"
+}
+
+#[locenv_test(testing)]
+fn rust_closure(host: &Host) {
+    assert_eq!(host.eval::<i64>("return testing.add(5)").unwrap(), 6);
+}
+
+#[locenv_test(self::testing, name = "foo")]
+fn module_name() {
+    "assert(foo.module_name() == 'foo')"
+}
+
+#[locenv_test(testing)]
+fn reference() {
+    r#"
+    local t = {}
+    local holder = testing.hold(t, print)
+
+    assert(holder.table == t)
+    assert(holder.value == print)
+    assert(testing.hold(t, 'foo').value == 'foo')
+    "#
+}
+
+#[locenv_test(testing)]
+fn reference_lifetime() {
+    r#"
+    local weak = setmetatable({}, { __mode = 'v' })
+    local holder
+
+    do
+      local t = {}
+      weak[1] = t
+      holder = testing.hold(t, nil)
+    end
+
+    collectgarbage()
+    collectgarbage()
+    assert(weak[1] ~= nil)
+
+    holder = nil
+    collectgarbage()
+    collectgarbage()
+    collectgarbage()
+    assert(weak[1] == nil)
+    "#
+}
+
+#[locenv_test(testing)]
+fn reference_in_coroutine() {
+    r#"
+    local t = {}
+    local co = coroutine.wrap(function()
+      for i = 1, 100 do
+        local holder = testing.hold(t, t)
+        assert(holder.table == t)
+        assert(holder.value == t)
+        holder = nil
+        collectgarbage()
+        coroutine.yield(i)
+      end
+    end)
+
+    for i = 1, 100 do
+      assert(co() == i)
+    end
+    "#
+}
+
+#[locenv_test(testing)]
+fn call() {
+    r#"
+    local a, b = testing.apply(function(v, s) return v * 2, s .. 'bar' end, 3)
+
+    assert(a == 6)
+    assert(b == 'foobar')
+    assert(testing.try_call(function() end) == 'ok')
+    "#
+}
+
+#[locenv_test(testing)]
+fn call_error() {
+    r#"
+    local e = { code = 1 }
+    local ok, r = pcall(testing.apply, function() error(e) end, 1)
+
+    assert(not ok)
+    assert(r == e)
+
+    r = testing.try_call(function() error('oops') end)
+
+    assert(r:find('oops', 1, true))
+    assert(r:find('stack traceback:', 1, true))
+    "#
+}
+
+#[locenv_test(testing)]
+fn table() {
+    r#"
+    assert(testing.sum({1, 2, 3}) == 6)
+    assert(not pcall(testing.sum, {1, 'a'}))
+
+    local keys = testing.keys({ a = 1, b = 2, c = 3 })
+
+    assert(#keys == 3 and keys[1] == 'a' and keys[3] == 'c')
+
+    local t = testing.build(setmetatable({ 1 }, { __index = { a = 5 } }))
+
+    assert(t.a == 5)
+    assert(t.b == nil)
+    assert(t[1] == 'x' and t[2] == 'b')
+    assert(t.len == 2 and t.raw_len == 1)
+    assert(t.first == 'x')
+    "#
+}
+
+#[locenv_test(testing)]
+fn load() {
+    r#"
+    assert(testing.eval('return 1 + 2') == 3)
+    assert(testing.eval_reader(string.rep(' ', 10000) .. 'return "foo"') == 'foo')
+
+    local ok, e = pcall(testing.eval, 'return +')
+
+    assert(not ok)
+    assert(e:find('^eval:1:'), e)
+
+    ok, e = pcall(testing.eval_binary, 'return 1')
+
+    assert(not ok)
+    assert(e:find('attempt to load a text chunk', 1, true), e)
+
+    ok, e = pcall(testing.eval_broken_reader)
+
+    assert(not ok)
+    assert(e:find('cannot read =broken: broken', 1, true), e)
+    "#
+}
+
+#[test]
+fn load_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut host = Host::new();
+
+    std::fs::write(directory.path().join("chunk.lua"), "return 'chunk'").unwrap();
+
+    host.set_working_directory(directory.path());
+    host.load("testing", testing::bootstrap).unwrap();
+    host.exec(
+        r#"
+        local testing = require 'testing'
+        local ok, e = pcall(testing.run_file, 'unknown.lua')
+
+        assert(testing.run_file('chunk.lua') == 'chunk')
+        assert(not ok and e:find('cannot open', 1, true), e)
+        "#,
+    )
+    .unwrap();
+}
+
+#[locenv_test(testing)]
+fn dump() {
+    r#"
+    assert(testing.roundtrip(function() return 'foo' end, true) == 'foo')
+    assert(testing.roundtrip(function() return 'bar' end, false) == 'bar')
+    assert(not pcall(testing.roundtrip, print, true))
+    assert(not pcall(testing.roundtrip, {}, true))
+    "#
+}
+
+#[locenv_test(testing)]
+fn embedded() {
+    r#"
+    local helpers = require 'testing.helpers'
+
+    assert(helpers.name() == 'testing.helpers')
+    assert(helpers.shout('foo') == 'FOO!')
+    assert(require('testing.util.strings').upper('a') == 'A')
+    "#
+}
+
+#[test]
+fn submodule() {
+    let mut host = Host::new();
+
+    host.load("testing", testing::bootstrap).unwrap();
+    host.exec(
+        r#"
+        local fs = require 'testing.fs'
+        local path = require 'testing.fs.path'
+
+        assert(fs.join('a', 'b') == 'a/b')
+        assert(fs.SEPARATOR == '/')
+        assert(fs.owner() == 'testing')
+        assert(fs.path == nil)
+        assert(path.extension('a.txt') == 'txt')
+        assert(require('testing').fs == nil)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn state() {
+    let first = host();
+    let second = host();
+
+    first
+        .exec("testing.visit() testing.visit() assert(require('testing.fs').visits() == 2)")
+        .unwrap();
+
+    assert_eq!(second.eval::<i64>("return testing.visit()").unwrap(), 1);
+
+    let error = message(second.exec("testing.invalid_state()").unwrap_err());
+
+    assert!(
+        error.contains("the state of module 'testing' is not alloc::string::String"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn userdata() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(5)
+
+        assert(testing.counter_value(counter) == 5)
+        assert(testing.is_counter(counter))
+        assert(not testing.is_counter(testing.hold({}, 1)))
+        assert(not testing.is_counter('counter'))
+        assert(not pcall(testing.counter_value, {}))
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn reentrant() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(1)
+
+        counter:inspect(function(v) assert(counter.value == v and #counter == v) end)
+        assert(counter:update(function(v) return v + 1 end) == 2)
+
+        local ok, e = pcall(counter.update, counter, function(v) return counter:increment() end)
+
+        assert(not ok)
+        assert(e:find('object already borrowed', 1, true), e)
+
+        ok, e = pcall(counter.inspect, counter, function(v) counter.value = 5 end)
+
+        assert(not ok)
+        assert(e:find('object already borrowed', 1, true), e)
+        assert(counter.value == 2)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn inline_userdata() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local vectors = {}
+
+        for i = 1, 10 do
+            local v = testing.new_vector(i, i * 2)
+
+            assert(v:aligned())
+            v:scale(2)
+            assert(v.x == i * 2 and v.y == i * 4)
+
+            vectors[i] = v
+        end
+        "#,
+    )
+    .unwrap();
+
+    host.exec("vectors = nil collectgarbage()").unwrap();
+
+    assert_eq!(DROPPED_VECTORS.load(Ordering::Relaxed), 10);
+
+    // The value must be dropped only once even if the finalizer is called by hand.
+    host.exec(
+        r#"
+        local v = testing.new_vector(1, 2)
+        local gc = debug.getmetatable(v).__gc
+
+        gc(v)
+        gc(v)
+
+        local ok, e = pcall(function() return v.x end)
+
+        assert(not ok)
+        assert(e:find('attempt to use a finalized Vector', 1, true), e)
+        "#,
+    )
+    .unwrap();
+
+    host.exec("collectgarbage()").unwrap();
+
+    assert_eq!(DROPPED_VECTORS.load(Ordering::Relaxed), 11);
+}
+
+#[test]
+fn finalized_userdata() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(1)
+
+        assert(getmetatable(counter) == 'testing.Counter')
+        assert(not pcall(setmetatable, counter, {}))
+
+        local gc = debug.getmetatable(counter).__gc
+
+        gc(counter)
+        gc(counter)
+
+        local ok, e = pcall(function() return counter.value end)
+
+        assert(not ok)
+        assert(e:find('attempt to use a finalized Counter', 1, true), e)
+        assert(not testing.is_counter(counter))
+
+        ok, e = pcall(testing.counter_value, counter)
+
+        assert(not ok)
+        assert(e:find('attempt to use a finalized Counter', 1, true), e)
+
+        counter = nil
+        collectgarbage()
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn external_size() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local function garbage()
+            collectgarbage()
+            collectgarbage('stop')
+
+            for i = 1, 1000 do
+                local _ = { i }
+            end
+
+            return collectgarbage('count')
+        end
+
+        local before = garbage()
+        local buffer = testing.new_buffer(64 * 1024 * 1024)
+
+        assert(collectgarbage('count') < before)
+
+        before = garbage()
+        buffer:reserve(128 * 1024 * 1024)
+
+        assert(collectgarbage('count') < before)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn user_value() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local emitter = testing.new_emitter()
+        local listeners = setmetatable({}, { __mode = 'v' })
+        local received
+
+        assert(not pcall(emitter.emit, emitter, 1))
+
+        listeners[1] = function(v) received = v end
+        emitter:on(listeners[1])
+        collectgarbage()
+
+        assert(listeners[1])
+        emitter:emit(5)
+        assert(received == 5)
+
+        emitter = nil
+        collectgarbage()
+        collectgarbage()
+
+        assert(listeners[1] == nil)
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(<testing::Buffer as Object>::USER_VALUES, 2);
+}
+
+#[test]
+fn call_result_error() {
+    let host = host();
+    let error = message(
+        host.exec("testing.apply(function() return 'foo' end, 1)")
+            .unwrap_err(),
+    );
+
+    assert!(
+        error.contains("bad result #1 (integer expected, got string)"),
+        "{}",
+        error
+    );
+
+    let error = message(
+        host.exec("testing.apply(function() return 1, {} end, 1)")
+            .unwrap_err(),
+    );
+
+    assert!(
+        error.contains("bad result #2 (string expected, got table)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn table_metamethod_error() {
+    let host = host();
+
+    host.exec(
+        r#"
+        local counter = testing.new_counter(1)
+        local t = setmetatable({}, {
+            __index = function() error('boom') end,
+            __newindex = function() error('bang') end,
+            __len = function() error('crash') end,
+        })
+
+        for _ = 1, 2 do
+            local ok, e = pcall(counter.lookup, counter, t, 'x')
+            assert(not ok and e:find('boom', 1, true), e)
+
+            ok, e = pcall(counter.store, counter, t, 'x', 1)
+            assert(not ok and e:find('bang', 1, true), e)
+
+            ok, e = pcall(counter.measure, counter, t)
+            assert(not ok and e:find('crash', 1, true), e)
+        end
+
+        assert(counter:increment() == 2)
+        assert(counter:lookup({ x = 3 }, 'x') == 3)
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn table_field_error() {
+    let host = host();
+    let error = message(host.exec("testing.build({ a = 'x' })").unwrap_err());
+
+    assert!(
+        error.contains("bad field 'a' (integer expected, got string)"),
+        "{}",
+        error
+    );
+}
@@ -90,9 +90,16 @@ pub struct ApiTable {
     pub lua_gettop: unsafe extern "C" fn(*mut LuaState) -> c_int,
     pub lua_settop: extern "C" fn(*mut LuaState, c_int),
 
-    pub lua_callk: unsafe extern "C" fn(*mut LuaState, c_int, c_int, isize, LuaContinuation),
-    pub lua_pcallk:
-        unsafe extern "C" fn(*mut LuaState, c_int, c_int, c_int, isize, LuaContinuation) -> c_int,
+    pub lua_callk:
+        unsafe extern "C" fn(*mut LuaState, c_int, c_int, isize, Option<LuaContinuation>),
+    pub lua_pcallk: unsafe extern "C" fn(
+        *mut LuaState,
+        c_int,
+        c_int,
+        c_int,
+        isize,
+        Option<LuaContinuation>,
+    ) -> c_int,
     pub lua_error: extern "C" fn(*mut LuaState) -> c_int,
     pub lua_warning: unsafe extern "C" fn(*mut LuaState, *const c_char, c_int),
 
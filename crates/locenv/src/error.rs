@@ -0,0 +1,95 @@
+use super::LuaValue;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::os::raw::c_int;
+
+/// Represents an error to raise on the Lua side.
+///
+/// The error is raised only after the function that produced it returned to the generated trampoline, which mean all
+/// Rust values was already dropped at that point.
+#[derive(Debug)]
+pub enum LuaError {
+    /// A runtime error with a message. The position where the error occurred is added at the beginning of the message
+    /// the same as `error` function in Lua.
+    Message(String),
+
+    /// A problem with argument `arg` of the current function. It will be raised as:
+    ///
+    /// `bad argument #arg to 'funcname' (message)`
+    Argument { arg: c_int, message: String },
+
+    /// A type mismatch on argument `arg` of the current function. `expected` is a "name" for the expected type. It will
+    /// be raised as:
+    ///
+    /// `bad argument #arg to 'funcname' (expected expected, got type)`
+    Type { arg: c_int, expected: String },
+
+    /// An error raised by a Lua function that was called with [`pcall()`](super::pcall). `value` is the original error
+    /// value and `traceback` is the stack traceback at the point of the error, which can be empty if the traceback is
+    /// not available (e.g. memory error). The original value will be raised again as-is.
+    Runtime { value: LuaValue, traceback: String },
+
+    /// A chunk cannot be loaded (e.g. a syntax error) from one of the `load_*` functions. The message already contains
+    /// the position of the error so it will be raised as-is.
+    Syntax(String),
+}
+
+impl LuaError {
+    /// Creates a [`LuaError::Argument`].
+    pub fn argument<M: Into<String>>(arg: c_int, message: M) -> Self {
+        Self::Argument {
+            arg,
+            message: message.into(),
+        }
+    }
+
+    /// Creates a [`LuaError::Type`].
+    pub fn r#type<E: Into<String>>(arg: c_int, expected: E) -> Self {
+        Self::Type {
+            arg,
+            expected: expected.into(),
+        }
+    }
+}
+
+impl Error for LuaError {}
+
+impl Display for LuaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Message(m) | Self::Syntax(m) => f.write_str(m),
+            Self::Argument { arg, message } => write!(f, "bad argument #{} ({})", arg, message),
+            Self::Type { arg, expected } => {
+                write!(f, "bad argument #{} ({} expected)", arg, expected)
+            }
+            Self::Runtime { value, traceback } => {
+                match value {
+                    LuaValue::String(v) => f.write_str(&String::from_utf8_lossy(v))?,
+                    LuaValue::Integer(v) => write!(f, "{}", v)?,
+                    LuaValue::Number(v) => write!(f, "{}", v)?,
+                    LuaValue::Nil => f.write_str("nil")?,
+                    LuaValue::Boolean(v) => write!(f, "{}", v)?,
+                    _ => f.write_str("(error object is not a string)")?,
+                }
+
+                if traceback.is_empty() {
+                    Ok(())
+                } else {
+                    write!(f, "\n{}", traceback)
+                }
+            }
+        }
+    }
+}
+
+impl From<String> for LuaError {
+    fn from(value: String) -> Self {
+        Self::Message(value)
+    }
+}
+
+impl From<&str> for LuaError {
+    fn from(value: &str) -> Self {
+        Self::Message(value.into())
+    }
+}
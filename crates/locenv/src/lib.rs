@@ -1,18 +1,51 @@
+// Every function in this crate receives the Lua state as a raw pointer, the same way the C API does.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+pub use self::error::LuaError;
+pub use self::load::{
+    dump_function, dump_function_to, load_bytes, load_file, load_reader, load_str, preload_chunks,
+    preload_module, EmbeddedChunk, LoadMode,
+};
+pub use self::reference::{FunctionRef, LuaRef, TableRef};
+pub use self::table::{Pairs, Sequence, Table};
+pub use self::value::{FromLua, LuaValue, Return, ToLua};
+
 use self::api::{ApiTable, BootstrapContext, LuaFunction, LuaReg, LuaState};
+use std::any::{type_name, Any};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::collections::LinkedList;
 use std::ffi::{c_void, CStr, CString};
-use std::mem::{size_of, transmute};
-use std::os::raw::{c_int, c_uint};
+use std::mem::{align_of, size_of, ManuallyDrop};
+use std::os::raw::{c_int, c_longlong, c_uint};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
 use std::ptr::{null, null_mut};
+use std::slice::from_raw_parts;
 use std::unreachable;
 
 pub mod api;
+mod error;
+mod load;
+mod reference;
+mod table;
+mod value;
 
 pub const LUAI_IS32INT: bool = (c_uint::MAX >> 30) >= 3;
 pub const LUAI_MAXSTACK: c_int = if LUAI_IS32INT { 1000000 } else { 15000 };
 pub const LUA_REGISTRYINDEX: c_int = -LUAI_MAXSTACK - 1000;
+pub const LUA_RIDX_MAINTHREAD: c_longlong = 1;
+pub const LUA_RIDX_GLOBALS: c_longlong = 2;
+
+pub const LUA_OK: c_int = 0;
+pub const LUA_YIELD: c_int = 1;
+pub const LUA_ERRRUN: c_int = 2;
+pub const LUA_ERRSYNTAX: c_int = 3;
+pub const LUA_ERRMEM: c_int = 4;
+pub const LUA_ERRERR: c_int = 5;
+
+pub const LUA_GCSTEP: c_int = 5;
 
+pub const LUA_TNONE: c_int = -1;
 pub const LUA_TNIL: c_int = 0;
 pub const LUA_TBOOLEAN: c_int = 1;
 pub const LUA_TLIGHTUSERDATA: c_int = 2;
@@ -25,19 +58,20 @@ pub const LUA_TTHREAD: c_int = 8;
 
 pub static mut API_TABLE: *const ApiTable = null();
 
-/// A helper macro that combine `error_with_message` and `format` together.
+/// A helper macro that combine [`LuaError::Message`] and `format` together.
 ///
 /// # Examples
 ///
 /// ```no_run
-/// # let lua: *mut locenv::api::LuaState = std::ptr::null_mut();
+/// # fn f() -> Result<(), locenv::LuaError> {
 /// # let e = "abc";
-/// locenv::error!(lua, "Something went wrong: {}", e);
+/// return Err(locenv::error!("Something went wrong: {}", e));
+/// # }
 /// ```
 #[macro_export]
 macro_rules! error {
-    ($lua:ident, $($arg:tt)*) => {
-        $crate::error_with_message($lua, &std::format!($($arg)*))
+    ($($arg:tt)*) => {
+        $crate::LuaError::Message(std::format!($($arg)*))
     }
 }
 
@@ -62,6 +96,17 @@ pub fn push_value(lua: *mut LuaState, index: c_int) {
     (api().lua_pushvalue)(lua, index);
 }
 
+/// Moves the top element into the given valid `index`, shifting up the elements above this `index` to open space.
+pub fn insert(lua: *mut LuaState, index: c_int) {
+    unsafe { (api().lua_rotate)(lua, index, 1) };
+}
+
+/// Removes the element at the given valid `index`, shifting down the elements above this `index` to fill the gap.
+pub fn remove(lua: *mut LuaState, index: c_int) {
+    unsafe { (api().lua_rotate)(lua, index, -1) };
+    pop(lua, 1);
+}
+
 /// Pushes a nil value onto the stack.
 pub fn push_nil(lua: *mut LuaState) {
     (api().lua_pushnil)(lua);
@@ -70,7 +115,7 @@ pub fn push_nil(lua: *mut LuaState) {
 /// Pushes a string onto the stack. The string can contain any binary data, including
 /// embedded zeros.
 pub fn push_str(lua: *mut LuaState, value: &str) {
-    unsafe { (api().lua_pushlstring)(lua, transmute(value.as_ptr()), value.len()) };
+    unsafe { (api().lua_pushlstring)(lua, value.as_ptr() as *const _, value.len()) };
 }
 
 /// Pushes a new function onto the stack.
@@ -85,12 +130,23 @@ pub fn push_fn(lua: *mut LuaState, value: LuaFunction, up: c_int) {
 /// Pushes a new closure onto the stack.
 ///
 /// The closure will be owned by the [`Context`] at the specified `index`.
-pub fn push_closure<T: Closure>(lua: *mut LuaState, context: c_int, value: T) {
+pub fn push_closure<T: Closure>(
+    lua: *mut LuaState,
+    context: c_int,
+    value: T,
+) -> Result<(), LuaError> {
     let context = abs_index(lua, context);
 
     push_value(lua, context);
-    create_userdata(lua, context, value, |_, _, _| {});
+
+    if let Err(e) = create_userdata(lua, context, value, 0, |_, _| {}) {
+        pop(lua, 1);
+        return Err(e);
+    }
+
     push_fn(lua, execute_closure::<T>, 2);
+
+    Ok(())
 }
 
 /// Creates a new empty table and pushes it onto the stack. Parameter `elements` is a hint for how many
@@ -105,25 +161,76 @@ pub fn create_table(lua: *mut LuaState, elements: c_int, fields: c_int) {
 /// Lua values.
 ///
 /// The userdata will be owned by the [`Context`] at the specified `index`.
-pub fn new_userdata<T: Object>(lua: *mut LuaState, context: c_int, value: T) {
-    create_userdata(lua, context, value, |lua, context, _| {
-        let methods = T::methods();
+pub fn new_userdata<T: Object>(
+    lua: *mut LuaState,
+    context: c_int,
+    value: T,
+) -> Result<(), LuaError> {
+    create_userdata(lua, context, value, T::USER_VALUES, setup_object::<T>)
+}
 
-        if methods.is_empty() {
-            return;
-        }
+/// Gets the userdata at the specified index that was created by [`new_userdata()`] from the current module. Returns
+/// an error if the value is not a `T`.
+///
+/// The current module is determined by the [`Context`] on the first upvalue of the running function, which is always
+/// the case for the functions that are generated by #\[function\] and #\[object\]. The object might be borrowed by the
+/// method that is currently running so use [`RefCell::try_borrow()`] or [`RefCell::try_borrow_mut()`] to access it.
+///
+/// **The returned reference is valid as long as the value at the specified index alive**.
+pub fn check_userdata<T: UserData>(
+    lua: *mut LuaState,
+    index: c_int,
+) -> Result<&'static RefCell<T>, LuaError> {
+    let context = Context::from_lua(lua, upvalue_index(1))?;
+    let data = context.get_userdata::<T>(lua, index)?;
+
+    Ok(unsafe { &(*data).value })
+}
 
-        create_table(lua, 0, methods.len() as _);
+/// Same as [`check_userdata()`] but returns [`None`] instead of an error if the value is not a `T`.
+pub fn test_userdata<T: UserData>(lua: *mut LuaState, index: c_int) -> Option<&'static RefCell<T>> {
+    let context = Context::from_lua(lua, upvalue_index(1)).ok()?;
+    let data = context.get_userdata::<T>(lua, index).ok()?;
 
-        for method in T::methods() {
-            push_value(lua, context);
-            (api().lua_pushlightuserdata)(lua, unsafe { transmute(method.function) });
-            push_fn(lua, invoke_method::<T>, 2);
-            set_field(lua, -2, method.name);
-        }
+    Some(unsafe { &(*data).value })
+}
 
-        set_field(lua, -2, "__index");
-    });
+/// Pushes onto the stack the `n`-th user value of the userdata at the specified index and returns the type of the
+/// pushed value. If the userdata does not have that value, pushes nil and returns [`LUA_TNONE`].
+pub fn get_user_value(lua: *mut LuaState, index: c_int, n: c_int) -> c_int {
+    unsafe { (api().lua_getiuservalue)(lua, index, n) }
+}
+
+/// Pops a value from the stack and sets it as the `n`-th user value of the userdata at the specified index. Returns
+/// `false` if the userdata does not have that value.
+pub fn set_user_value(lua: *mut LuaState, index: c_int, n: c_int) -> bool {
+    unsafe { (api().lua_setiuservalue)(lua, index, n) != 0 }
+}
+
+/// Reports the changes of [`UserData::external_size()`] of the userdata at the specified index to Lua GC. The userdata
+/// must be created by [`new_userdata()`] from the current module (see [`check_userdata()`]).
+///
+/// Lua GC will perform an extra step proportional to the growth since the last report. Nothing is reported if the
+/// object is currently borrowed exclusively.
+pub fn update_external_size<T: UserData>(lua: *mut LuaState, index: c_int) -> Result<(), LuaError> {
+    let context = Context::from_lua(lua, upvalue_index(1))?;
+    let data = context.get_userdata::<T>(lua, index)?;
+
+    unsafe { (*data).update_external_size(lua) };
+
+    Ok(())
+}
+
+/// Pushes onto the stack the table that contains all methods of the object `T`.
+///
+/// This is the same table that the userdata created by [`new_userdata()`] looks up for its methods, which means any
+/// functions added to this table will also be available as the methods of `T`.
+pub fn push_methods<T: Object>(lua: *mut LuaState, context: c_int) -> Result<(), LuaError> {
+    push_metatable::<T, _>(lua, context, setup_object::<T>)?;
+    get_field(lua, -1, "__methods");
+    remove(lua, -2);
+
+    Ok(())
 }
 
 /// Does the equivalent to t[key] = v, where t is the value at the given `index` and v is the value
@@ -170,38 +277,43 @@ pub fn is_none_or_nil(lua: *mut LuaState, index: c_int) -> bool {
 }
 
 /// If the function argument `arg` is a string, returns this string. If this argument is absent or
-/// is nil, returns [`None`]. Otherwise, raises an error.
+/// is nil, returns [`None`]. Otherwise, returns an error.
 ///
-/// This function uses [`to_string`] to get its result, so all conversions and caveats of that
-/// function apply here.
-pub fn opt_string(lua: *mut LuaState, arg: c_int) -> Option<String> {
-    if is_none_or_nil(lua, arg) {
-        None
-    } else {
-        Some(check_string(lua, arg))
-    }
+/// A number is also accepted and converted to a string.
+pub fn opt_string(lua: *mut LuaState, arg: c_int) -> Result<Option<String>, LuaError> {
+    check(lua, arg)
 }
 
 /// Checks whether the function argument `arg` is a string and returns this string.
-pub fn check_string(lua: *mut LuaState, arg: c_int) -> String {
-    let data = unsafe { (api().aux_checklstring)(lua, arg, null_mut()) };
-    let raw = unsafe { CStr::from_ptr(data) };
+///
+/// A number is also accepted and converted to a string.
+pub fn check_string(lua: *mut LuaState, arg: c_int) -> Result<String, LuaError> {
+    check(lua, arg)
+}
 
-    raw.to_str().unwrap().into()
+/// Checks whether the function argument `arg` can be converted to `T` and returns the converted value.
+///
+/// Use [`Option`] as `T` for an optional argument.
+pub fn check<T: FromLua>(lua: *mut LuaState, arg: c_int) -> Result<T, LuaError> {
+    T::from_lua(lua, arg)
 }
 
 /// Converts the Lua value at the given `index` to a string.
 ///
 /// The Lua value must be a string or a number; otherwise, the function returns [`None`]. If the value is a number,
-/// then this function also changes the actual value in the stack to a string.
+/// then this function also changes the actual value in the stack to a string. Invalid UTF-8 sequences are replaced
+/// with `U+FFFD`.
 pub fn to_string(lua: *mut LuaState, index: c_int) -> Option<String> {
-    let value = unsafe { (api().lua_tolstring)(lua, index, null_mut()) };
+    let mut len = 0;
+    let value = unsafe { (api().lua_tolstring)(lua, index, &mut len) };
 
     if value.is_null() {
         return None;
     }
 
-    unsafe { Some(CStr::from_ptr(value).to_str().unwrap().into()) }
+    let value = unsafe { from_raw_parts(value as *const u8, len) };
+
+    Some(String::from_utf8_lossy(value).into_owned())
 }
 
 /// Pushes onto the stack the value t[key], where t is the value at the given `index`. As in Lua, this function may
@@ -220,56 +332,213 @@ pub fn set_metatable(lua: *mut LuaState, index: c_int) {
     (api().lua_setmetatable)(lua, index);
 }
 
-/// Raises a type error for the argument `arg` of the function that called it, using a standard message; `expect` is
-/// a "name" for the expected type.
-pub fn type_error(lua: *mut LuaState, arg: c_int, expect: &str) -> ! {
-    let expect = CString::new(expect).unwrap();
+/// Calls the function at the specified `index` in protected mode with `args` and converts its results to `R`.
+///
+/// An error raised by the function is returned as [`LuaError::Runtime`], which contains the original error value and
+/// the traceback at the point of the error. Returning it from the module function will raise the original value again.
+pub fn pcall<A, R>(lua: *mut LuaState, index: c_int, args: A) -> Result<R, LuaError>
+where
+    A: ToLua,
+    R: FromLua,
+{
+    let index = abs_index(lua, index);
+    let top = unsafe { (api().lua_gettop)(lua) };
+
+    push_value(lua, index);
 
-    unsafe { (api().aux_typeerror)(lua, arg, expect.as_ptr()) };
-    unreachable!();
+    let count = args.to_lua(lua);
+
+    protected_call(lua, count, R::SLOTS)?;
+
+    // The stack is reset before the error is raised so the index must not leak into the error.
+    let result = R::from_lua(lua, top + 1)
+        .map_err(|e| slot_error(lua, e, |i| format!("result #{}", i - top)));
+
+    (api().lua_settop)(lua, top);
+
+    result
 }
 
-/// Raises an error reporting a problem with argument `arg` of the function that called it, using a standard message
-/// that includes `comment` as a comment:
+/// Calls the function at the specified `index` with `args` and converts its results to `R`. Use [`pcall()`] unless
+/// there is a reason not to.
 ///
-/// `bad argument #arg to 'funcname' (comment)`
-pub fn argument_error(lua: *mut LuaState, arg: c_int, comment: &str) -> ! {
-    let comment = CString::new(comment).unwrap();
+/// # Safety
+///
+/// An error raised by the function will be propagated with `longjmp`, so there must be no live Rust values that need
+/// to be dropped on any frames between this call and the Lua boundary.
+pub unsafe fn call<A, R>(lua: *mut LuaState, index: c_int, args: A) -> Result<R, LuaError>
+where
+    A: ToLua,
+    R: FromLua,
+{
+    let top = (api().lua_gettop)(lua);
 
-    unsafe { (api().aux_argerror)(lua, arg, comment.as_ptr()) };
-    unreachable!();
+    push_value(lua, index);
+
+    let count = args.to_lua(lua);
+
+    (api().lua_callk)(lua, count, R::SLOTS, 0, None);
+
+    let result = R::from_lua(lua, top + 1)
+        .map_err(|e| slot_error(lua, e, |i| format!("result #{}", i - top)));
+
+    (api().lua_settop)(lua, top);
+
+    result
+}
+
+/// Invokes `f` with the [`Context`] at the specified `index`, then raises a Lua error if `f` returns an error or
+/// panic. This function is used by #\[loader\] attribute.
+///
+/// This function must be the last thing to call in the `extern "C"` function.
+pub fn invoke<F>(lua: *mut LuaState, context: c_int, f: F) -> c_int
+where
+    F: FnOnce(&Context) -> Result<c_int, LuaError>,
+{
+    let context = match Context::from_lua(lua, context) {
+        Ok(v) => v,
+        Err(e) => unsafe { raise(lua, e) },
+    };
+
+    catch_panic(lua, context.module_name(), || f(context))
+}
+
+/// Invokes `f`, then raises a Lua error if `f` returns an error or panic. The error message for the panic will contain
+/// the name of the `module` and the panic message.
+///
+/// This function must be the last thing to call in the `extern "C"` function.
+pub fn catch_panic<F>(lua: *mut LuaState, module: &str, f: F) -> c_int
+where
+    F: FnOnce() -> Result<c_int, LuaError>,
+{
+    let error = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => return v,
+        Ok(Err(e)) => e,
+        Err(e) => error!("module '{}' panicked: {}", module, panic_message(&*e)),
+    };
+
+    unsafe { raise(lua, error) }
 }
 
-/// Raises a Lua error with the specified message.
-pub fn error_with_message(lua: *mut LuaState, message: &str) -> ! {
-    let format = CString::new("%s").unwrap();
-    let message = CString::new(message).unwrap();
+/// Gets the message from a panic `payload`. This function is used by #\[loader\] attribute.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(v) = payload.downcast_ref::<&str>() {
+        v
+    } else if let Some(v) = payload.downcast_ref::<String>() {
+        v.as_str()
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Raises the specified error as a Lua error. The `error` is dropped before the error is raised.
+///
+/// # Safety
+///
+/// Lua raises an error with `longjmp`, so there must be no live Rust values that need to be dropped on any frames
+/// between this call and the Lua boundary.
+pub unsafe fn raise(lua: *mut LuaState, error: LuaError) -> ! {
+    // Move the message into Lua before raising so nothing is leaked.
+    match error {
+        LuaError::Message(m) => {
+            (api().aux_where)(lua, 1);
+            push_str(lua, &m);
+            drop(m);
+            (api().lua_concat)(lua, 2);
+            self::error(lua)
+        }
+        LuaError::Argument { arg, message } => {
+            push_str(lua, &message);
+            drop(message);
+            (api().aux_argerror)(lua, arg, (api().lua_tolstring)(lua, -1, null_mut()));
+        }
+        LuaError::Type { arg, expected } => {
+            push_str(lua, &expected);
+            drop(expected);
+            (api().aux_typeerror)(lua, arg, (api().lua_tolstring)(lua, -1, null_mut()));
+        }
+        LuaError::Runtime { value, traceback } => {
+            drop(traceback);
+            value.to_lua(lua);
+            self::error(lua)
+        }
+        LuaError::Syntax(m) => {
+            push_str(lua, &m);
+            drop(m);
+            self::error(lua)
+        }
+    }
 
-    unsafe { (api().aux_error)(lua, format.as_ptr(), message.as_ptr()) };
     unreachable!();
 }
 
 /// Raises a Lua error, using the value on the top of the stack as the error object.
-pub fn error(lua: *mut LuaState) -> ! {
+///
+/// # Safety
+///
+/// Lua raises an error with `longjmp`, so there must be no live Rust values that need to be dropped on any frames
+/// between this call and the Lua boundary.
+pub unsafe fn error(lua: *mut LuaState) -> ! {
     (api().lua_error)(lua);
     unreachable!();
 }
 
 /// A trait to allow Rust object to be able to get collected by Lua GC.
 pub trait UserData: 'static {
+    /// Store the value directly inside the memory block of the userdata instead of a pointer to a boxed value.
+    ///
+    /// This saves a heap allocation and an indirection for each object and allows Lua GC to see the real size of the
+    /// object, which is suitable for a type that has many small instances.
+    const INLINE: bool = false;
+
     /// Gets a unique name for this type within this module.
     fn type_name() -> &'static str;
+
+    /// Gets the size of the memory owned by this object outside the userdata (e.g. the content of a `Vec`), in bytes.
+    ///
+    /// Lua GC cannot see this memory so it will run too rarely for the object that owns a large buffer. The size is
+    /// reported to Lua GC when the object is created and re-checked after each method that takes `&mut self`. Use
+    /// [`update_external_size()`] if the size was changed in other ways.
+    ///
+    /// Only the growth is reported, in whole KiB, since Lua has no API to report the memory that was freed. Shrinking
+    /// the size or dropping the object does not reduce the pressure on Lua GC; the next growth is counted from the
+    /// smaller size.
+    fn external_size(&self) -> usize {
+        0
+    }
 }
 
 /// A trait for implement Lua closure.
 pub trait Closure: UserData {
-    fn call(&mut self, lua: *mut LuaState) -> c_int;
+    fn call(&mut self, lua: *mut LuaState) -> Result<c_int, LuaError>;
 }
 
 /// A trait for implement Lua object.
+///
+/// Use #\[object\] attribute from `locenv-macros` to implement this trait from an `impl` block.
 pub trait Object: UserData {
+    /// The number of user values to reserve on the userdata, which can be accessed with [`get_user_value()`] and
+    /// [`set_user_value()`].
+    ///
+    /// The values are kept alive as long as the object is alive, which is suitable for the Lua values that the object
+    /// needs to keep (e.g. callbacks or child objects) without creating a registry reference.
+    const USER_VALUES: c_int = 0;
+
     /// Gets a set of available methods.
     fn methods() -> &'static [MethodEntry<Self>];
+
+    /// Gets a set of metamethods to install on the metatable of this object.
+    fn metamethods() -> &'static [MetamethodEntry<Self>] {
+        &[]
+    }
+
+    /// Gets a set of available properties.
+    ///
+    /// When reading a field from the object, the methods take precedence over the properties. Assigning a field that
+    /// is not a property will raise an error.
+    fn properties() -> &'static [PropertyEntry<Self>] {
+        &[]
+    }
 }
 
 /// Represents a method of a Lua object.
@@ -289,14 +558,146 @@ pub struct MethodEntry<T: ?Sized> {
     ///
     /// ```no_run
     /// # let lua: *mut locenv::api::LuaState = std::ptr::null_mut();
-    /// locenv::check_string(lua, 2);
+    /// locenv::check_string(lua, 2).unwrap();
     /// ```
     ///
     /// Notice the index is `2`, not `1`.
     pub function: Method<T>,
 }
 
-pub type Method<T> = fn(&mut T, *mut LuaState) -> c_int;
+/// A pointer to function that implements a method of a Lua object.
+///
+/// The object is borrowed for the duration of the call, which means a method that calls back into Lua cannot be
+/// re-entered with the same object unless both calls borrow it shared. The conflicting call will raise "object
+/// already borrowed" instead.
+pub enum Method<T: ?Sized> {
+    /// A method that takes `&self`.
+    Shared(fn(&T, *mut LuaState) -> Result<c_int, LuaError>),
+
+    /// A method that takes `&mut self`.
+    Exclusive(fn(&mut T, *mut LuaState) -> Result<c_int, LuaError>),
+}
+
+impl<T: UserData> Method<T> {
+    fn invoke(&self, data: &Storage<T>, lua: *mut LuaState) -> Result<c_int, LuaError> {
+        match self {
+            Self::Shared(f) => f(&*borrow(&data.value)?, lua),
+            Self::Exclusive(f) => {
+                let result = f(&mut *borrow_mut(&data.value)?, lua);
+                data.update_external_size(lua);
+                result
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Method<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Method<T> {}
+
+/// Represents a property of a Lua object.
+pub struct PropertyEntry<T: ?Sized> {
+    pub name: &'static str,
+
+    /// A pointer to function to push the value of this property. The first index is the object and the second index
+    /// is the name of the property. The property is write-only if this is [`None`].
+    pub getter: Option<Method<T>>,
+
+    /// A pointer to function to set the value of this property. The first index is the object, the second index is the
+    /// name of the property and the third index is the new value. The property is read-only if this is [`None`].
+    pub setter: Option<Method<T>>,
+}
+
+/// Represents a metamethod of a Lua object.
+pub struct MetamethodEntry<T: ?Sized> {
+    pub event: Metamethod,
+
+    /// A pointer to function for this metamethod.
+    ///
+    /// The stack is the same as [`MethodEntry::function`] except for the binary events (see
+    /// [`Metamethod::is_binary()`]). For the binary events, the object that owns this metamethod is always at the first
+    /// index and the other operand is at the second index, followed by a boolean at the third index that indicates
+    /// whether the object was the right operand. Let say the user invoke your metamethod as the following:
+    ///
+    /// ```notrust
+    /// local r = 10 - v
+    /// ```
+    ///
+    /// Within this function the first index will be `v`, the second index will be `10` and the third index will be
+    /// `true`.
+    pub function: Method<T>,
+}
+
+/// Represents an event of the metamethod.
+///
+/// `__gc`, `__index`, `__newindex`, `__name`, `__metatable` and `__methods` are reserved by this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metamethod {
+    ToString,
+    Eq,
+    Lt,
+    Le,
+    Len,
+    Call,
+    Concat,
+    Close,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Unm,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    BNot,
+}
+
+impl Metamethod {
+    /// Gets the key of this event in the metatable.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ToString => "__tostring",
+            Self::Eq => "__eq",
+            Self::Lt => "__lt",
+            Self::Le => "__le",
+            Self::Len => "__len",
+            Self::Call => "__call",
+            Self::Concat => "__concat",
+            Self::Close => "__close",
+            Self::Add => "__add",
+            Self::Sub => "__sub",
+            Self::Mul => "__mul",
+            Self::Div => "__div",
+            Self::Mod => "__mod",
+            Self::Pow => "__pow",
+            Self::Unm => "__unm",
+            Self::IDiv => "__idiv",
+            Self::BAnd => "__band",
+            Self::BOr => "__bor",
+            Self::BXor => "__bxor",
+            Self::Shl => "__shl",
+            Self::Shr => "__shr",
+            Self::BNot => "__bnot",
+        }
+    }
+
+    /// Returns `true` if the object can be either operand of this event.
+    pub fn is_binary(self) -> bool {
+        !matches!(
+            self,
+            Self::ToString | Self::Len | Self::Call | Self::Close | Self::Unm | Self::BNot
+        )
+    }
+}
 
 /// Represents a function to add to a Lua table.
 pub struct FunctionEntry<'name> {
@@ -309,36 +710,47 @@ pub struct Context {
     locenv: *const c_void,
     module_name: String,
     working_directory: PathBuf,
+    state: RefCell<Option<Box<dyn Any>>>,
 }
 
 impl Context {
+    /// Creates a new context from the data locenv passed to the module bootstrap. This method is used by
+    /// #\[loader\] attribute.
+    ///
+    /// # Safety
+    ///
+    /// `bootstrap` must point to a valid [`BootstrapContext`].
     pub unsafe fn new(bootstrap: *const BootstrapContext) -> Self {
         Self {
             locenv: (*bootstrap).locenv,
             module_name: CStr::from_ptr((*bootstrap).name).to_str().unwrap().into(),
-            working_directory: CStr::from_ptr((*bootstrap).name).to_str().unwrap().into(),
+            working_directory: CStr::from_ptr((*bootstrap).working_directory)
+                .to_str()
+                .unwrap()
+                .into(),
+            state: RefCell::new(None),
         }
     }
 
     /// Gets a reference to the context from Lua stack at the specified index.
     ///
     /// **The returned reference is valid as long as the value at the specified index alive**.
-    pub fn from_lua(lua: *mut LuaState, index: c_int) -> &'static Self {
+    pub fn from_lua(lua: *mut LuaState, index: c_int) -> Result<&'static Self, LuaError> {
         // Get userdata.
         let ud = (api().lua_touserdata)(lua, index);
 
         if ud.is_null() {
-            error!(lua, "expect a userdata at #{}", index);
+            return Err(error!("expect a userdata at #{}", index));
         }
 
         // Get type name.
         if (api().lua_getmetatable)(lua, index) == 0 {
-            error!(lua, "expect a module context at #{}", index);
+            return Err(error!("expect a module context at #{}", index));
         }
 
         if get_field(lua, -1, "__name") != LUA_TSTRING {
             pop(lua, 2);
-            error!(lua, "invalid metatable for the value at #{}", index);
+            return Err(error!("invalid metatable for the value at #{}", index));
         }
 
         let r#type = to_string(lua, -1).unwrap();
@@ -347,14 +759,13 @@ impl Context {
 
         // Check if it is a Context.
         if r#type == "locenv" || r#type.contains('.') {
-            error!(lua, "expect a module context at #{}", index);
+            return Err(error!("expect a module context at #{}", index));
         }
 
         // Dereference.
-        let context: *mut Self = null_mut();
+        let context = unsafe { (ud as *const *mut Self).read_unaligned() };
 
-        unsafe { ud.copy_to_nonoverlapping(transmute(&context), size_of::<*mut Self>()) };
-        unsafe { &*context }
+        Ok(unsafe { &*context })
     }
 
     /// Gets name of the current module.
@@ -394,30 +805,100 @@ impl Context {
         }
     }
 
+    /// Attaches a state to the current module, replacing the previous one. This method is used by #\[loader\] attribute
+    /// when `state` is specified.
+    ///
+    /// The state will be dropped together with the context when the Lua VM is closed.
+    pub fn set_state<T: Any>(&self, state: T) {
+        *self.state.borrow_mut() = Some(Box::new(state));
+    }
+
+    /// Gets the state of the current module. Returns an error if the module has no state, the state is not `T` or it is
+    /// already borrowed.
+    pub fn state<T: Any>(&self) -> Result<RefMut<'_, T>, LuaError> {
+        let state = match self.state.try_borrow_mut() {
+            Ok(v) => v,
+            Err(_) => {
+                return Err(error!(
+                    "the state of module '{}' is already borrowed",
+                    self.module_name
+                ))
+            }
+        };
+
+        match state.as_ref() {
+            Some(v) if v.is::<T>() => {}
+            Some(_) => {
+                return Err(error!(
+                    "the state of module '{}' is not {}",
+                    self.module_name,
+                    type_name::<T>()
+                ))
+            }
+            None => return Err(error!("module '{}' has no state", self.module_name)),
+        }
+
+        Ok(RefMut::map(state, |v| {
+            v.as_mut().unwrap().downcast_mut().unwrap()
+        }))
+    }
+
     /// A finalizer for [`Context`]. This method is used by #\[loader\] attribute.
     pub extern "C" fn finalize(lua: *mut LuaState) -> c_int {
         // Get a pointer to context.
         let table = unsafe { (api().aux_checklstring)(lua, upvalue_index(1), null_mut()) };
         let ud = unsafe { (api().aux_checkudata)(lua, 1, table) };
-        let raw: *mut Self = null_mut();
-
-        unsafe { ud.copy_to_nonoverlapping(transmute(&raw), size_of::<*mut Self>()) };
+        let raw = unsafe { (ud as *const *mut Self).read_unaligned() };
+        let name = unsafe { CStr::from_ptr(table) }.to_str().unwrap();
 
         // Destroy.
-        unsafe { Box::from_raw(raw) };
+        catch_panic(lua, name, || {
+            drop(unsafe { Box::from_raw(raw) });
+            Ok(0)
+        })
+    }
 
-        0
+    fn get_userdata<T: UserData>(
+        &self,
+        lua: *mut LuaState,
+        index: c_int,
+    ) -> Result<*mut Storage<T>, LuaError> {
+        match self.get_storage::<T>(lua, index)? {
+            Some(v) => Ok(v),
+            None => Err(error!("attempt to use a finalized {}", T::type_name())),
+        }
     }
 
-    fn get_userdata<T: UserData>(&self, lua: *mut LuaState, index: c_int) -> *mut T {
+    /// Same as [`Context::get_userdata()`] but returns [`None`] if the userdata was already finalized.
+    fn get_storage<T: UserData>(
+        &self,
+        lua: *mut LuaState,
+        index: c_int,
+    ) -> Result<Option<*mut Storage<T>>, LuaError> {
         let table = self.get_type_name::<T>();
         let table = CString::new(table).unwrap();
-        let ud = unsafe { (api().aux_checkudata)(lua, index, table.as_ptr()) };
-        let raw: *mut T = null_mut();
+        let ud = unsafe { (api().aux_testudata)(lua, index, table.as_ptr()) };
 
-        unsafe { ud.copy_to_nonoverlapping(transmute(&raw), size_of::<*mut T>()) };
+        if ud.is_null() {
+            Err(LuaError::r#type(index, T::type_name()))
+        } else if T::INLINE {
+            let data =
+                unsafe { ud.add(ud.align_offset(align_of::<Storage<T>>())) } as *mut Storage<T>;
+
+            if unsafe { (*data).finalized.get() } {
+                Ok(None)
+            } else {
+                Ok(Some(data))
+            }
+        } else {
+            let data = unsafe { (ud as *const *mut Storage<T>).read_unaligned() };
 
-        raw
+            if data.is_null() {
+                Ok(None)
+            } else {
+                Ok(Some(data))
+            }
+        }
     }
 
     fn get_type_name<T: UserData>(&self) -> String {
@@ -425,54 +906,373 @@ impl Context {
     }
 }
 
-fn create_userdata<T, S>(lua: *mut LuaState, context: c_int, value: T, setup: S)
+fn create_userdata<T, S>(
+    lua: *mut LuaState,
+    context: c_int,
+    value: T,
+    user_values: c_int,
+    setup: S,
+) -> Result<(), LuaError>
 where
     T: UserData,
-    S: FnOnce(*mut LuaState, c_int, &T),
+    S: FnOnce(*mut LuaState, c_int),
 {
-    // Get table name.
+    // Get metatable.
     let context = abs_index(lua, context);
-    let table = Context::from_lua(lua, context).get_type_name::<T>();
-    let table = CString::new(table).unwrap();
+
+    push_metatable::<T, S>(lua, context, setup)?;
+
+    // Report the memory that Lua cannot see. Only the reported bytes are stored so the remaining bytes will be reported
+    // together with the later growths.
+    let external = report_allocation(lua, value.external_size());
 
     // Push the userdata.
-    let boxed = Box::into_raw(Box::new(value));
-    let size = size_of::<*mut T>();
-    let up = (api().lua_newuserdatauv)(lua, size, 1);
+    let value = Storage {
+        value: ManuallyDrop::new(RefCell::new(value)),
+        external: Cell::new(external),
+        finalized: Cell::new(false),
+    };
+
+    if T::INLINE {
+        // Lua does not guarantee the alignment of T so reserve enough space to align it ourself.
+        let align = align_of::<Storage<T>>();
+        let size = size_of::<Storage<T>>() + align - 1;
+        let up = (api().lua_newuserdatauv)(lua, size, user_values);
+
+        unsafe { (up.add(up.align_offset(align)) as *mut Storage<T>).write(value) };
+    } else {
+        let boxed = Box::into_raw(Box::new(value));
+        let size = size_of::<*mut Storage<T>>();
+        let up = (api().lua_newuserdatauv)(lua, size, user_values);
 
-    unsafe { up.copy_from_nonoverlapping(transmute(&boxed), size) };
+        unsafe { (up as *mut *mut Storage<T>).write_unaligned(boxed) };
+    }
 
     // Associate the userdata with metatable.
+    insert(lua, -2);
+    set_metatable(lua, -2);
+
+    Ok(())
+}
+
+fn push_metatable<T, S>(lua: *mut LuaState, context: c_int, setup: S) -> Result<(), LuaError>
+where
+    T: UserData,
+    S: FnOnce(*mut LuaState, c_int),
+{
+    let context = abs_index(lua, context);
+    let table = Context::from_lua(lua, context)?.get_type_name::<T>();
+    let table = CString::new(table).unwrap();
+
     if unsafe { (api().aux_newmetatable)(lua, table.as_ptr()) } == 1 {
         push_value(lua, context);
         push_fn(lua, free_userdata::<T>, 1);
         set_field(lua, -2, "__gc");
-        unsafe { setup(lua, context, &*boxed) };
+
+        // Hide the metatable from Lua so the finalizer cannot be called by hand.
+        get_field(lua, -1, "__name");
+        set_field(lua, -2, "__metatable");
+        setup(lua, context);
     }
 
-    set_metatable(lua, -2);
+    Ok(())
+}
+
+fn setup_object<T: Object>(lua: *mut LuaState, context: c_int) {
+    let methods = T::methods();
+
+    create_table(lua, 0, methods.len() as _);
+
+    for method in methods {
+        push_value(lua, context);
+        (api().lua_pushlightuserdata)(lua, &method.function as *const Method<T> as _);
+        push_fn(lua, invoke_method::<T>, 2);
+        set_field(lua, -2, method.name);
+    }
+
+    push_value(lua, -1);
+    set_field(lua, -3, "__methods");
+
+    if T::properties().is_empty() {
+        set_field(lua, -2, "__index");
+    } else {
+        push_value(lua, context);
+        insert(lua, -2);
+        push_fn(lua, index_object::<T>, 2);
+        set_field(lua, -2, "__index");
+
+        push_value(lua, context);
+        push_fn(lua, newindex_object::<T>, 1);
+        set_field(lua, -2, "__newindex");
+    }
+
+    for method in T::metamethods() {
+        let invoker = if method.event.is_binary() {
+            invoke_operator::<T>
+        } else {
+            invoke_method::<T>
+        };
+
+        push_value(lua, context);
+        (api().lua_pushlightuserdata)(lua, &method.function as *const Method<T> as _);
+        push_fn(lua, invoker, 2);
+        set_field(lua, -2, method.event.name());
+    }
 }
 
 extern "C" fn execute_closure<T: Closure>(lua: *mut LuaState) -> c_int {
-    let context = Context::from_lua(lua, upvalue_index(1));
-    let closure = context.get_userdata::<T>(lua, upvalue_index(2));
+    invoke(lua, upvalue_index(1), |context| {
+        let closure = context.get_userdata::<T>(lua, upvalue_index(2))?;
+
+        let closure = unsafe { &*closure };
+        let result = borrow_mut(&closure.value)?.call(lua);
 
-    unsafe { (*closure).call(lua) }
+        closure.update_external_size(lua);
+
+        result
+    })
 }
 
 extern "C" fn invoke_method<T: Object>(lua: *mut LuaState) -> c_int {
-    let context = Context::from_lua(lua, upvalue_index(1));
-    let method = (api().lua_touserdata)(lua, upvalue_index(2));
-    let method: Method<T> = unsafe { transmute(method) };
-    let data = context.get_userdata::<T>(lua, 1);
+    invoke(lua, upvalue_index(1), |context| {
+        let method = (api().lua_touserdata)(lua, upvalue_index(2)) as *const Method<T>;
+        let data = context.get_userdata::<T>(lua, 1)?;
+
+        unsafe { (*method).invoke(&*data, lua) }
+    })
+}
+
+extern "C" fn invoke_operator<T: Object>(lua: *mut LuaState) -> c_int {
+    invoke(lua, upvalue_index(1), |context| {
+        let method = (api().lua_touserdata)(lua, upvalue_index(2)) as *const Method<T>;
+
+        // Move the object to the first index.
+        (api().lua_settop)(lua, 2);
+
+        let data = match context.get_userdata::<T>(lua, 1) {
+            Ok(v) => {
+                false.to_lua(lua);
+                v
+            }
+            Err(_) => {
+                let v = context.get_userdata::<T>(lua, 2)?;
+                unsafe { (api().lua_rotate)(lua, 1, 1) };
+                true.to_lua(lua);
+                v
+            }
+        };
 
-    unsafe { method(&mut *data, lua) }
+        unsafe { (*method).invoke(&*data, lua) }
+    })
+}
+
+extern "C" fn index_object<T: Object>(lua: *mut LuaState) -> c_int {
+    invoke(lua, upvalue_index(1), |context| {
+        let data = context.get_userdata::<T>(lua, 1)?;
+
+        // Look up the methods first.
+        push_value(lua, 2);
+
+        if unsafe { (api().lua_rawget)(lua, upvalue_index(2)) } != LUA_TNIL {
+            return Ok(1);
+        }
+
+        pop(lua, 1);
+
+        // Look up the properties.
+        let property = match find_property::<T>(lua, 2) {
+            Some(v) => v,
+            None => {
+                push_nil(lua);
+                return Ok(1);
+            }
+        };
+
+        match property.getter {
+            Some(f) => f.invoke(unsafe { &*data }, lua),
+            None => Err(error!("property '{}' is write-only", property.name)),
+        }
+    })
+}
+
+extern "C" fn newindex_object<T: Object>(lua: *mut LuaState) -> c_int {
+    invoke(lua, upvalue_index(1), |context| {
+        let data = context.get_userdata::<T>(lua, 1)?;
+        let property = match find_property::<T>(lua, 2) {
+            Some(v) => v,
+            None => {
+                let key = to_string(lua, 2).unwrap_or_else(|| "?".into());
+                return Err(error!("unknown property '{}' on {}", key, T::type_name()));
+            }
+        };
+
+        match property.setter {
+            Some(f) => f.invoke(unsafe { &*data }, lua).map(|_| 0),
+            None => Err(error!("property '{}' is read-only", property.name)),
+        }
+    })
+}
+
+fn find_property<T: Object>(lua: *mut LuaState, key: c_int) -> Option<&'static PropertyEntry<T>> {
+    if (api().lua_type)(lua, key) != LUA_TSTRING {
+        return None;
+    }
+
+    // Compare the raw bytes since the key can be any Lua string.
+    let mut len = 0;
+    let key = unsafe { (api().lua_tolstring)(lua, key, &mut len) };
+    let key = unsafe { from_raw_parts(key as *const u8, len) };
+
+    T::properties().iter().find(|p| p.name.as_bytes() == key)
+}
+
+/// The memory block of the userdata.
+struct Storage<T> {
+    /// The value is dropped by [`free_userdata()`] without freeing the memory block in the inline mode.
+    value: ManuallyDrop<RefCell<T>>,
+
+    /// The last size that was reported to Lua GC.
+    external: Cell<usize>,
+
+    /// Indicates the value was already dropped. The boxed mode uses a null pointer on the userdata instead since the
+    /// storage is freed together with the value.
+    finalized: Cell<bool>,
+}
+
+impl<T: UserData> Storage<T> {
+    fn update_external_size(&self, lua: *mut LuaState) {
+        let size = match self.value.try_borrow() {
+            Ok(v) => v.external_size(),
+            Err(_) => return,
+        };
+
+        let previous = self.external.get();
+
+        if size <= previous {
+            self.external.set(size);
+        } else {
+            // Keep the remaining bytes unreported so the small growths can add up.
+            self.external
+                .set(previous + report_allocation(lua, size - previous));
+        }
+    }
+}
+
+/// Performs a GC step as if `size` bytes was allocated by Lua, then returns the number of bytes that was reported. Lua
+/// has no way to report the freed memory so the shrinking is not reported.
+fn report_allocation(lua: *mut LuaState, size: usize) -> usize {
+    let kb = (size / 1024).min(c_int::MAX as usize);
+
+    if kb > 0 {
+        unsafe { (api().lua_gc)(lua, LUA_GCSTEP, kb as c_int) };
+    }
+
+    kb * 1024
+}
+
+fn borrow<T: ?Sized>(data: &RefCell<T>) -> Result<Ref<'_, T>, LuaError> {
+    data.try_borrow()
+        .map_err(|_| error!("object already borrowed"))
+}
+
+fn borrow_mut<T: ?Sized>(data: &RefCell<T>) -> Result<RefMut<'_, T>, LuaError> {
+    data.try_borrow_mut()
+        .map_err(|_| error!("object already borrowed"))
+}
+
+/// Calls the function below `count` arguments on the top of the stack in protected mode, then leaves `results` values
+/// in place of the function. On error, the function and the arguments are popped and the error is returned as
+/// [`LuaError::Runtime`].
+fn protected_call(lua: *mut LuaState, count: c_int, results: c_int) -> Result<(), LuaError> {
+    let base = unsafe { (api().lua_gettop)(lua) } - count;
+
+    push_fn(lua, message_handler, 0);
+    insert(lua, base);
+
+    let status = unsafe { (api().lua_pcallk)(lua, count, results, base, 0, None) };
+
+    if status != LUA_OK {
+        let value = LuaValue::from_lua(lua, -1).unwrap_or(LuaValue::Nil);
+        let traceback = unsafe {
+            (api().lua_rawgetp)(lua, LUA_REGISTRYINDEX, &TRACEBACK as *const u8 as _);
+            (api().lua_pushnil)(lua);
+            (api().lua_rawsetp)(lua, LUA_REGISTRYINDEX, &TRACEBACK as *const u8 as _);
+            to_string(lua, -1).unwrap_or_default()
+        };
+
+        (api().lua_settop)(lua, base - 1);
+
+        return Err(LuaError::Runtime { value, traceback });
+    }
+
+    remove(lua, base);
+
+    Ok(())
+}
+
+/// Converts the error from [`FromLua::from_lua()`] on a stack slot that is not an argument of the current function into
+/// [`LuaError::Message`]. `name` receives the index in the error and returns the name of that slot (e.g. `result #1`).
+fn slot_error<N>(lua: *mut LuaState, error: LuaError, name: N) -> LuaError
+where
+    N: FnOnce(c_int) -> String,
+{
+    match error {
+        LuaError::Type { arg, expected } => {
+            let actual =
+                unsafe { CStr::from_ptr((api().lua_typename)(lua, (api().lua_type)(lua, arg))) };
+
+            error!(
+                "bad {} ({} expected, got {})",
+                name(arg),
+                expected,
+                actual.to_string_lossy()
+            )
+        }
+        LuaError::Argument { arg, message } => error!("bad {} ({})", name(arg), message),
+        e => e,
+    }
+}
+
+/// A key in the registry to store the traceback from [`message_handler()`].
+static TRACEBACK: u8 = 0;
+
+extern "C" fn message_handler(lua: *mut LuaState) -> c_int {
+    unsafe {
+        (api().aux_traceback)(lua, lua, null(), 1);
+        (api().lua_rawsetp)(lua, LUA_REGISTRYINDEX, &TRACEBACK as *const u8 as _);
+    }
+
+    1
 }
 
 extern "C" fn free_userdata<T: UserData>(lua: *mut LuaState) -> c_int {
-    let context = Context::from_lua(lua, upvalue_index(1));
-    unsafe { Box::from_raw(context.get_userdata::<T>(lua, 1)) };
-    0
+    invoke(lua, upvalue_index(1), |context| {
+        // The finalizer can run again on the object that was resurrected by another finalizer.
+        let data = match context.get_storage::<T>(lua, 1)? {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+
+        if unsafe { (*data).value.try_borrow_mut() }.is_err() {
+            return Err(error!("object already borrowed"));
+        }
+
+        // Mark the storage before dropping the value in case of the value panic.
+        if T::INLINE {
+            unsafe { (*data).finalized.set(true) };
+        } else {
+            let ud = (api().lua_touserdata)(lua, 1);
+            unsafe { (ud as *mut *mut Storage<T>).write_unaligned(null_mut()) };
+        }
+
+        unsafe { ManuallyDrop::drop(&mut (*data).value) };
+
+        if !T::INLINE {
+            drop(unsafe { Box::from_raw(data) });
+        }
+
+        Ok(0)
+    })
 }
 
 fn api() -> &'static ApiTable {
@@ -0,0 +1,366 @@
+use super::api::{LuaFunction, LuaState};
+use super::{
+    abs_index, api, get_field, invoke, pop, push_fn, push_value, set_field, to_string,
+    upvalue_index, Context, FunctionRef, LuaError, LuaValue, ToLua, LUA_ERRSYNTAX, LUA_OK,
+    LUA_REGISTRYINDEX, LUA_TFUNCTION, LUA_TTABLE,
+};
+use std::any::Any;
+use std::ffi::{c_void, CString};
+use std::io::{ErrorKind, Read, Write};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr::null;
+
+/// Specifies which kind of chunk can be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Only Lua source code.
+    Text,
+
+    /// Only precompiled chunk (e.g. from [`dump_function()`]).
+    Binary,
+
+    /// Either source code or precompiled chunk.
+    Any,
+}
+
+impl LoadMode {
+    fn as_cstr(self) -> &'static [u8] {
+        match self {
+            Self::Text => b"t\0",
+            Self::Binary => b"b\0",
+            Self::Any => b"bt\0",
+        }
+    }
+}
+
+/// Loads a Lua source code as a function. `name` is the name of the chunk that used in the error messages and the
+/// debug information (e.g. `=mychunk`, see `load` in the Lua manual for more information).
+///
+/// Returns [`LuaError::Syntax`] if the code contains a syntax error.
+pub fn load_str(lua: *mut LuaState, chunk: &str, name: &str) -> Result<FunctionRef, LuaError> {
+    load_bytes(lua, chunk.as_bytes(), name, LoadMode::Text)
+}
+
+/// Loads a chunk from the memory as a function. See [`load_str()`] for the meaning of `name`.
+///
+/// Returns [`LuaError::Syntax`] if the chunk cannot be loaded.
+pub fn load_bytes(
+    lua: *mut LuaState,
+    chunk: &[u8],
+    name: &str,
+    mode: LoadMode,
+) -> Result<FunctionRef, LuaError> {
+    let name = chunk_name(name)?;
+    let status = unsafe {
+        (api().aux_loadbufferx)(
+            lua,
+            chunk.as_ptr() as *const c_char,
+            chunk.len(),
+            name.as_ptr(),
+            mode.as_cstr().as_ptr() as *const c_char,
+        )
+    };
+
+    pop_function(lua, status)
+}
+
+/// Loads a file as a function. A relative `path` is relative to [`Context::working_directory()`].
+///
+/// Returns [`LuaError::Syntax`] if the file cannot be loaded or [`LuaError::Message`] if the file cannot be read.
+pub fn load_file<P: AsRef<Path>>(
+    lua: *mut LuaState,
+    context: &Context,
+    path: P,
+    mode: LoadMode,
+) -> Result<FunctionRef, LuaError> {
+    let path = context.working_directory().join(path);
+    let path = match path.to_str().and_then(|v| CString::new(v).ok()) {
+        Some(v) => v,
+        None => return Err(crate::error!("invalid path {}", path.display())),
+    };
+
+    let status = unsafe {
+        (api().aux_loadfilex)(lua, path.as_ptr(), mode.as_cstr().as_ptr() as *const c_char)
+    };
+
+    pop_function(lua, status)
+}
+
+/// Loads a chunk from `reader` as a function. See [`load_str()`] for the meaning of `name`.
+///
+/// Returns [`LuaError::Syntax`] if the chunk cannot be loaded or [`LuaError::Message`] if `reader` returned an error.
+/// A panic in `reader` is propagated after the Lua side was cleaned up.
+pub fn load_reader<R: Read>(
+    lua: *mut LuaState,
+    reader: R,
+    name: &str,
+    mode: LoadMode,
+) -> Result<FunctionRef, LuaError> {
+    let name = chunk_name(name)?;
+    let mut data = Reader {
+        reader,
+        buffer: vec![0; 8192],
+        error: None,
+        panic: None,
+    };
+
+    let status = unsafe {
+        (api().lua_load)(
+            lua,
+            read::<R>,
+            &mut data as *mut Reader<R> as *mut c_void,
+            name.as_ptr(),
+            mode.as_cstr().as_ptr() as *const c_char,
+        )
+    };
+
+    if let Some(e) = data.panic {
+        pop(lua, 1);
+        resume_unwind(e);
+    } else if let Some(e) = data.error {
+        pop(lua, 1);
+        return Err(crate::error!(
+            "cannot read {}: {}",
+            name.to_string_lossy(),
+            e
+        ));
+    }
+
+    pop_function(lua, status)
+}
+
+/// Dumps the Lua function at the specified `index` as a precompiled chunk, which can be loaded again with
+/// [`load_bytes()`] or [`load_reader()`]. The debug information is removed from the chunk if `strip` is `true`.
+///
+/// The chunk can be loaded only by the same Lua version.
+pub fn dump_function(lua: *mut LuaState, index: c_int, strip: bool) -> Result<Vec<u8>, LuaError> {
+    let mut chunk = Vec::new();
+
+    dump_function_to(lua, index, strip, &mut chunk)?;
+
+    Ok(chunk)
+}
+
+/// Dumps the Lua function at the specified `index` to `writer`. See [`dump_function()`] for more information.
+///
+/// Returns [`LuaError::Message`] if `writer` returned an error. A panic in `writer` is propagated.
+pub fn dump_function_to<W: Write>(
+    lua: *mut LuaState,
+    index: c_int,
+    strip: bool,
+    writer: W,
+) -> Result<(), LuaError> {
+    if (api().lua_type)(lua, index) != LUA_TFUNCTION {
+        return Err(LuaError::r#type(index, "function"));
+    }
+
+    let mut data = Writer {
+        writer,
+        error: None,
+        panic: None,
+    };
+
+    push_value(lua, index);
+
+    let status = unsafe {
+        (api().lua_dump)(
+            lua,
+            write::<W>,
+            &mut data as *mut Writer<W> as *mut c_void,
+            strip.into(),
+        )
+    };
+
+    pop(lua, 1);
+
+    if let Some(e) = data.panic {
+        resume_unwind(e);
+    } else if let Some(e) = data.error {
+        return Err(crate::error!("cannot write the function: {}", e));
+    } else if status != 0 {
+        return Err(LuaError::argument(index, "unable to dump given function"));
+    }
+
+    // Flush here instead of inside the callback so the error is reported properly.
+    if let Err(e) = data.writer.flush() {
+        return Err(crate::error!("cannot write the function: {}", e));
+    }
+
+    Ok(())
+}
+
+/// A Lua source file that was embedded into the module with `embed_lua!` from `locenv-macros`.
+#[derive(Debug)]
+pub struct EmbeddedChunk {
+    /// The name of the submodule without the module name (e.g. `helpers` for `mymodule.helpers`).
+    pub name: &'static str,
+
+    /// The path of the file relative to the crate, which is used as the chunk name.
+    pub path: &'static str,
+
+    pub source: &'static str,
+}
+
+/// Registers `chunks` to `package.preload` as the submodules of the current module so `require '<module>.<name>'` will
+/// load the chunk. The chunk is compiled when it is required for the first time. This function is used by #\[module\]
+/// attribute.
+///
+/// The chunk receives the full name of the submodule as its first argument, the same as a Lua file that loaded by
+/// `require`.
+pub fn preload_chunks(
+    lua: *mut LuaState,
+    context: c_int,
+    chunks: &'static [EmbeddedChunk],
+) -> Result<(), LuaError> {
+    let context = abs_index(lua, context);
+    let module = Context::from_lua(lua, context)?.module_name();
+
+    // Get package.preload.
+    if get_field(lua, LUA_REGISTRYINDEX, "_PRELOAD") != LUA_TTABLE {
+        pop(lua, 1);
+        return Err(crate::error!("package library is not loaded"));
+    }
+
+    for chunk in chunks {
+        push_value(lua, context);
+        (api().lua_pushlightuserdata)(lua, chunk as *const EmbeddedChunk as *mut u8);
+        push_fn(lua, load_embedded, 2);
+        set_field(lua, -2, &format!("{}.{}", module, chunk.name));
+    }
+
+    pop(lua, 1);
+
+    Ok(())
+}
+
+/// Registers `loader` to `package.preload` as a submodule `name` of the current module, which means
+/// `require '<module>.<name>'` will invoke `loader` with the context on the first upvalue. This function is used by
+/// #\[module\] attribute.
+pub fn preload_module(
+    lua: *mut LuaState,
+    context: c_int,
+    name: &str,
+    loader: LuaFunction,
+) -> Result<(), LuaError> {
+    let context = abs_index(lua, context);
+    let module = Context::from_lua(lua, context)?.module_name();
+
+    if get_field(lua, LUA_REGISTRYINDEX, "_PRELOAD") != LUA_TTABLE {
+        pop(lua, 1);
+        return Err(crate::error!("package library is not loaded"));
+    }
+
+    push_value(lua, context);
+    push_fn(lua, loader, 1);
+    set_field(lua, -2, &format!("{}.{}", module, name));
+    pop(lua, 1);
+
+    Ok(())
+}
+
+extern "C" fn load_embedded(lua: *mut LuaState) -> c_int {
+    invoke(lua, upvalue_index(1), |_| {
+        let chunk = (api().lua_touserdata)(lua, upvalue_index(2)) as *const EmbeddedChunk;
+        let chunk = unsafe { &*chunk };
+        let name = to_string(lua, 1).unwrap_or_default();
+        let function = load_str(lua, chunk.source, &format!("@{}", chunk.path))?;
+        let result: LuaValue = function.call(lua, name)?;
+
+        Ok(result.to_lua(lua))
+    })
+}
+
+struct Reader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    error: Option<std::io::Error>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+unsafe extern "C" fn read<R: Read>(
+    _: *mut LuaState,
+    data: *mut c_void,
+    size: *mut usize,
+) -> *const c_char {
+    let data = &mut *(data as *mut Reader<R>);
+    let result = catch_unwind(AssertUnwindSafe(|| loop {
+        match data.reader.read(&mut data.buffer) {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            r => break r,
+        }
+    }));
+
+    // Returning NULL or zero size will end the chunk.
+    *size = 0;
+
+    match result {
+        Ok(Ok(0)) => null(),
+        Ok(Ok(n)) => {
+            *size = n;
+            data.buffer.as_ptr() as *const c_char
+        }
+        Ok(Err(e)) => {
+            data.error = Some(e);
+            null()
+        }
+        Err(e) => {
+            data.panic = Some(e);
+            null()
+        }
+    }
+}
+
+struct Writer<W> {
+    writer: W,
+    error: Option<std::io::Error>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+unsafe extern "C" fn write<W: Write>(
+    _: *mut LuaState,
+    p: *const c_void,
+    size: usize,
+    data: *mut c_void,
+) -> c_int {
+    let data = &mut *(data as *mut Writer<W>);
+    let buffer = std::slice::from_raw_parts(p as *const u8, size);
+    let result = catch_unwind(AssertUnwindSafe(|| data.writer.write_all(buffer)));
+
+    // Returning non-zero will stop the dump.
+    match result {
+        Ok(Ok(_)) => 0,
+        Ok(Err(e)) => {
+            data.error = Some(e);
+            1
+        }
+        Err(e) => {
+            data.panic = Some(e);
+            1
+        }
+    }
+}
+
+fn chunk_name(name: &str) -> Result<CString, LuaError> {
+    CString::new(name).map_err(|_| crate::error!("chunk name cannot contains NUL"))
+}
+
+/// Pops the result of the load function with the specified `status`.
+fn pop_function(lua: *mut LuaState, status: c_int) -> Result<FunctionRef, LuaError> {
+    if status == LUA_OK {
+        let function = FunctionRef::from_index(lua, -1);
+        pop(lua, 1);
+        return function;
+    }
+
+    let message = to_string(lua, -1).unwrap_or_default();
+
+    pop(lua, 1);
+
+    if status == LUA_ERRSYNTAX {
+        Err(LuaError::Syntax(message))
+    } else {
+        Err(LuaError::Message(message))
+    }
+}
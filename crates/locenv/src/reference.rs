@@ -0,0 +1,207 @@
+use super::api::LuaState;
+use super::{
+    api, pcall, pop, push_value, FromLua, LuaError, ToLua, LUA_REGISTRYINDEX, LUA_RIDX_MAINTHREAD,
+    LUA_TFUNCTION, LUA_TTABLE,
+};
+use std::fmt::{Debug, Formatter};
+use std::os::raw::c_int;
+
+/// A reference to a Lua value that keeps the value alive in the registry until it is dropped.
+///
+/// Unlike the values on the stack, the reference can be stored in Rust (e.g. inside an [`Object`](super::Object)) and
+/// pushed back later. **The reference must be dropped before the Lua state is closed.**
+///
+/// [`Clone`], [`PartialEq`] and [`Drop`] cannot receive the running thread so they work on the stack of the main
+/// thread, which lives as long as the Lua state. This is safe even when a coroutine is running because the main thread
+/// is then suspended inside a C call (e.g. `coroutine.resume`) and these operations only touch the registry with raw
+/// accesses, leaving its stack as they found it.
+pub struct LuaRef {
+    lua: *mut LuaState,
+    id: c_int,
+}
+
+impl LuaRef {
+    /// Pops the value on the top of the stack and stores it in the registry.
+    pub fn new(lua: *mut LuaState) -> Self {
+        let id = unsafe { (api().aux_ref)(lua, LUA_REGISTRYINDEX) };
+
+        Self {
+            lua: main_thread(lua),
+            id,
+        }
+    }
+
+    /// Creates a reference to the value at the specified index. The stack is leaved unchanged.
+    pub fn from_index(lua: *mut LuaState, index: c_int) -> Self {
+        push_value(lua, index);
+        Self::new(lua)
+    }
+
+    /// Pushes the referenced value onto the stack and returns its type.
+    pub fn push(&self, lua: *mut LuaState) -> c_int {
+        unsafe { (api().lua_rawgeti)(lua, LUA_REGISTRYINDEX, self.id as _) }
+    }
+
+    /// Gets the main thread with room for the specified number of values on its stack. The suspended thread may already
+    /// use all of the slots that Lua guarantees to its current C function. Returns [`None`] if the stack cannot grow.
+    fn main_stack(&self, n: c_int) -> Option<*mut LuaState> {
+        if unsafe { (api().lua_checkstack)(self.lua, n) } == 0 {
+            None
+        } else {
+            Some(self.lua)
+        }
+    }
+}
+
+impl Drop for LuaRef {
+    fn drop(&mut self) {
+        // Leak the value instead of panicking since the reference may be dropped during unwinding.
+        if let Some(lua) = self.main_stack(1) {
+            unsafe { (api().aux_unref)(lua, LUA_REGISTRYINDEX, self.id) };
+        }
+    }
+}
+
+impl Clone for LuaRef {
+    fn clone(&self) -> Self {
+        let lua = self
+            .main_stack(2)
+            .expect("cannot grow the stack of the main thread");
+
+        self.push(lua);
+        Self::new(lua)
+    }
+}
+
+impl PartialEq for LuaRef {
+    /// Returns `true` if both references point to the same value without invoking `__eq`.
+    fn eq(&self, other: &Self) -> bool {
+        let lua = self
+            .main_stack(2)
+            .expect("cannot grow the stack of the main thread");
+
+        self.push(lua);
+        other.push(lua);
+
+        let result = unsafe { (api().lua_rawequal)(lua, -1, -2) } != 0;
+
+        pop(lua, 2);
+
+        result
+    }
+}
+
+impl Debug for LuaRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LuaRef").field(&self.id).finish()
+    }
+}
+
+impl FromLua for LuaRef {
+    const TYPE_NAME: &'static str = "value";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        Ok(Self::from_index(lua, index))
+    }
+}
+
+impl ToLua for LuaRef {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        self.push(lua);
+        1
+    }
+}
+
+macro_rules! typed_ref {
+    ($(#[$attr:meta])* $name:ident, $type:ident, $type_name:literal) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name(LuaRef);
+
+        impl $name {
+            /// Creates a reference to the value at the specified index. Returns [`LuaError::Type`] if the value has
+            /// a different type.
+            pub fn from_index(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+                if (api().lua_type)(lua, index) == $type {
+                    Ok(Self(LuaRef::from_index(lua, index)))
+                } else {
+                    Err(LuaError::r#type(index, $type_name))
+                }
+            }
+
+            /// Pushes the referenced value onto the stack.
+            pub fn push(&self, lua: *mut LuaState) {
+                self.0.push(lua);
+            }
+
+            /// Gets the untyped reference.
+            pub fn into_inner(self) -> LuaRef {
+                self.0
+            }
+        }
+
+        impl AsRef<LuaRef> for $name {
+            fn as_ref(&self) -> &LuaRef {
+                &self.0
+            }
+        }
+
+        impl FromLua for $name {
+            const TYPE_NAME: &'static str = $type_name;
+
+            fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+                Self::from_index(lua, index)
+            }
+        }
+
+        impl ToLua for $name {
+            fn to_lua(self, lua: *mut LuaState) -> c_int {
+                self.push(lua);
+                1
+            }
+        }
+    };
+}
+
+typed_ref!(
+    /// A [`LuaRef`] that always points to a table.
+    TableRef,
+    LUA_TTABLE,
+    "table"
+);
+
+typed_ref!(
+    /// A [`LuaRef`] that always points to a function.
+    FunctionRef,
+    LUA_TFUNCTION,
+    "function"
+);
+
+impl FunctionRef {
+    /// Calls the function with [`pcall()`] and converts its results to `R`.
+    pub fn call<A: ToLua, R: FromLua>(&self, lua: *mut LuaState, args: A) -> Result<R, LuaError> {
+        self.push(lua);
+
+        let result = pcall(lua, -1, args);
+
+        pop(lua, 1);
+
+        result
+    }
+}
+
+/// Gets the main thread so the reference can be released even if the thread that created it was already collected.
+fn main_thread(lua: *mut LuaState) -> *mut LuaState {
+    let main = unsafe {
+        (api().lua_rawgeti)(lua, LUA_REGISTRYINDEX, LUA_RIDX_MAINTHREAD);
+        (api().lua_tothread)(lua, -1)
+    };
+
+    pop(lua, 1);
+
+    if main.is_null() {
+        lua
+    } else {
+        main
+    }
+}
@@ -0,0 +1,317 @@
+use super::api::LuaState;
+use super::value::element_error;
+use super::{
+    abs_index, api, create_table, pop, protected_call, push_fn, push_nil, push_value, remove,
+    slot_error, FromLua, LuaError, ToLua, LUA_TNUMBER, LUA_TSTRING, LUA_TTABLE,
+};
+use std::ffi::CStr;
+use std::marker::PhantomData;
+use std::os::raw::c_int;
+
+/// A view of the table on the stack.
+///
+/// The view holds the absolute index of the table, which means **it is valid only as long as that slot is alive**. Use
+/// [`TableRef`](super::TableRef) to keep the table beyond that.
+///
+/// The methods that are not prefixed with `raw` may invoke metamethods, which are run in protected mode so the error
+/// raised by them will be returned as [`LuaError::Runtime`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Table {
+    lua: *mut LuaState,
+    index: c_int,
+}
+
+impl Table {
+    /// Creates a new table on the top of the stack. `elements` and `fields` are a hint for how many elements the table
+    /// will have as a sequence and as other fields.
+    pub fn new(lua: *mut LuaState, elements: c_int, fields: c_int) -> Self {
+        create_table(lua, elements, fields);
+
+        Self {
+            lua,
+            index: abs_index(lua, -1),
+        }
+    }
+
+    /// Creates a view of the table at the specified index. Returns [`LuaError::Type`] if the value is not a table.
+    pub fn from_index(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        if (api().lua_type)(lua, index) != LUA_TTABLE {
+            return Err(LuaError::r#type(index, "table"));
+        }
+
+        Ok(Self {
+            lua,
+            index: abs_index(lua, index),
+        })
+    }
+
+    /// Gets the absolute index of the table on the stack.
+    pub fn index(&self) -> c_int {
+        self.index
+    }
+
+    /// Gets `t[key]`.
+    pub fn get<K: ToLua, V: FromLua>(&self, key: K) -> Result<V, LuaError> {
+        key.to_lua(self.lua);
+        push_fn(self.lua, get_table, 0);
+        push_value(self.lua, self.index);
+        push_value(self.lua, -3);
+
+        if let Err(e) = protected_call(self.lua, 2, 1) {
+            pop(self.lua, 1);
+            return Err(e);
+        }
+
+        self.pop_field()
+    }
+
+    /// Sets `t[key] = value`.
+    pub fn set<K: ToLua, V: ToLua>(&self, key: K, value: V) -> Result<(), LuaError> {
+        push_fn(self.lua, set_table, 0);
+        push_value(self.lua, self.index);
+        key.to_lua(self.lua);
+        value.to_lua(self.lua);
+        protected_call(self.lua, 3, 0)
+    }
+
+    /// Gets `t[key]` without invoking metamethods.
+    pub fn raw_get<K: ToLua, V: FromLua>(&self, key: K) -> Result<V, LuaError> {
+        key.to_lua(self.lua);
+        push_value(self.lua, -1);
+        unsafe { (api().lua_rawget)(self.lua, self.index) };
+        self.pop_field()
+    }
+
+    /// Sets `t[key] = value` without invoking metamethods.
+    pub fn raw_set<K: ToLua, V: ToLua>(&self, key: K, value: V) {
+        key.to_lua(self.lua);
+        value.to_lua(self.lua);
+        unsafe { (api().lua_rawset)(self.lua, self.index) };
+    }
+
+    /// Gets `t[n]` without invoking metamethods.
+    pub fn raw_get_index<V: FromLua>(&self, n: i64) -> Result<V, LuaError> {
+        n.to_lua(self.lua);
+        unsafe { (api().lua_rawgeti)(self.lua, self.index, n) };
+        self.pop_field()
+    }
+
+    /// Sets `t[n] = value` without invoking metamethods.
+    pub fn raw_set_index<V: ToLua>(&self, n: i64, value: V) {
+        value.to_lua(self.lua);
+        unsafe { (api().lua_rawseti)(self.lua, self.index, n) };
+    }
+
+    /// Gets `#t`.
+    pub fn len(&self) -> Result<i64, LuaError> {
+        push_fn(self.lua, get_length, 0);
+        push_value(self.lua, self.index);
+        protected_call(self.lua, 1, 1)?;
+
+        let len = i64::from_lua(self.lua, -1);
+        pop(self.lua, 1);
+        len
+    }
+
+    /// Gets `#t` without invoking metamethods.
+    pub fn raw_len(&self) -> u64 {
+        unsafe { (api().lua_rawlen)(self.lua, self.index) }
+    }
+
+    /// Returns `true` if `#t` is zero, without invoking metamethods.
+    pub fn is_empty(&self) -> bool {
+        self.raw_len() == 0
+    }
+
+    /// Iterates over all fields of the table in an unspecified order the same as `pairs` in Lua, without invoking
+    /// `__pairs`.
+    ///
+    /// The iterator keeps the current key on the stack. The values pushed while iterating must be popped before the
+    /// next iteration and the table must not be modified other than assigning existing fields.
+    pub fn pairs<K: FromLua, V: FromLua>(&self) -> Pairs<K, V> {
+        Pairs {
+            table: *self,
+            key: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Iterates over `t[1]` to `t[#t]` without invoking metamethods.
+    pub fn sequence<V: FromLua>(&self) -> Sequence<V> {
+        Sequence {
+            table: *self,
+            next: 1,
+            len: self.raw_len(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Converts the value on the top of the stack then pops it together with the key below it.
+    fn pop_field<V: FromLua>(&self) -> Result<V, LuaError> {
+        let value = V::from_lua(self.lua, -1)
+            .map_err(|e| slot_error(self.lua, e, |_| key_name(self.lua, -2)));
+        pop(self.lua, 2);
+        value
+    }
+}
+
+impl FromLua for Table {
+    const TYPE_NAME: &'static str = "table";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        Self::from_index(lua, index)
+    }
+}
+
+impl ToLua for Table {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        push_value(lua, self.index);
+        1
+    }
+}
+
+/// An iterator that returned from [`Table::pairs()`].
+pub struct Pairs<K, V> {
+    table: Table,
+    key: c_int, // 0 = not started, -1 = finished, otherwise the index of the current key.
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K: FromLua, V: FromLua> Iterator for Pairs<K, V> {
+    type Item = Result<(K, V), LuaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lua = self.table.lua;
+
+        // Push the previous key.
+        if self.key < 0 {
+            return None;
+        } else if self.key == 0 {
+            push_nil(lua);
+        } else {
+            push_value(lua, self.key);
+        }
+
+        if unsafe { (api().lua_next)(lua, self.table.index) } == 0 {
+            self.finish();
+            return None;
+        }
+
+        // Convert the key and the value.
+        let key = K::from_lua(lua, -2);
+        let value = V::from_lua(lua, -1);
+
+        // Keep the key in the slot for the next iteration.
+        pop(lua, 1);
+
+        if self.key == 0 {
+            self.key = abs_index(lua, -1);
+        } else {
+            unsafe { (api().lua_copy)(lua, -1, self.key) };
+            pop(lua, 1);
+        }
+
+        Some(match (key, value) {
+            (Ok(k), Ok(v)) => Ok((k, v)),
+            (Err(_), _) => Err(field_error(self.table.index, "key", K::TYPE_NAME)),
+            (_, Err(_)) => Err(field_error(self.table.index, "value", V::TYPE_NAME)),
+        })
+    }
+}
+
+impl<K, V> Pairs<K, V> {
+    fn finish(&mut self) {
+        if self.key > 0 {
+            remove(self.table.lua, self.key);
+        }
+
+        self.key = -1;
+    }
+}
+
+impl<K, V> Drop for Pairs<K, V> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// An iterator that returned from [`Table::sequence()`].
+pub struct Sequence<V> {
+    table: Table,
+    next: u64,
+    len: u64,
+    phantom: PhantomData<V>,
+}
+
+impl<V: FromLua> Iterator for Sequence<V> {
+    type Item = Result<V, LuaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.len {
+            return None;
+        }
+
+        let index = self.next;
+        let value = self
+            .table
+            .raw_get_index(index as _)
+            .map_err(|_| element_error(self.table.index, index, V::TYPE_NAME));
+
+        self.next += 1;
+
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.len + 1 - self.next) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+extern "C" fn get_table(lua: *mut LuaState) -> c_int {
+    unsafe { (api().lua_gettable)(lua, 1) };
+    1
+}
+
+extern "C" fn set_table(lua: *mut LuaState) -> c_int {
+    (api().lua_settable)(lua, 1);
+    0
+}
+
+extern "C" fn get_length(lua: *mut LuaState) -> c_int {
+    let len = unsafe { (api().aux_len)(lua, 1) };
+
+    unsafe { (api().lua_pushinteger)(lua, len) };
+    1
+}
+
+/// Gets the name of the key at the specified index for the error message.
+fn key_name(lua: *mut LuaState, index: c_int) -> String {
+    let r#type = (api().lua_type)(lua, index);
+
+    if r#type != LUA_TSTRING && r#type != LUA_TNUMBER {
+        let name = unsafe { CStr::from_ptr((api().lua_typename)(lua, r#type)) };
+        return format!("field ({})", name.to_string_lossy());
+    }
+
+    // Convert a copy so the key is not changed to a string.
+    push_value(lua, index);
+
+    let mut len = 0;
+    let data = unsafe { (api().lua_tolstring)(lua, -1, &mut len) };
+    let key =
+        String::from_utf8_lossy(unsafe { std::slice::from_raw_parts(data as *const u8, len) })
+            .into_owned();
+
+    pop(lua, 1);
+
+    if r#type == LUA_TSTRING {
+        format!("field '{}'", key)
+    } else {
+        format!("field [{}]", key)
+    }
+}
+
+fn field_error(table: c_int, field: &str, expected: &str) -> LuaError {
+    LuaError::argument(table, format!("invalid {} ({} expected)", field, expected))
+}
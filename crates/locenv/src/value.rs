@@ -0,0 +1,500 @@
+use super::api::LuaState;
+use super::{
+    abs_index, api, create_table, pop, push_nil, push_value, FunctionRef, LuaError, LuaRef, Table,
+    TableRef, LUA_TBOOLEAN, LUA_TFUNCTION, LUA_TLIGHTUSERDATA, LUA_TNIL, LUA_TNUMBER, LUA_TSTRING,
+    LUA_TTABLE, LUA_TTHREAD, LUA_TUSERDATA,
+};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::hash::Hash;
+use std::os::raw::c_int;
+use std::slice::from_raw_parts;
+
+/// Represents any Lua value.
+///
+/// Tables, functions, full userdata and threads are not copied into Rust. Instead, they hold a reference to the value
+/// in the registry, which keeps the value alive until the [`LuaValue`] is dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(TableRef),
+    Function(FunctionRef),
+    UserData(LuaRef),
+    LightUserData(*mut c_void),
+    Thread(LuaRef),
+}
+
+/// A trait to convert a Lua value on the stack into a Rust value.
+pub trait FromLua: Sized {
+    /// A name of the expected Lua type, which is used in the error message when the conversion failed.
+    const TYPE_NAME: &'static str;
+
+    /// A number of stack slots this type reads. Only tuples occupy more than one slot.
+    const SLOTS: c_int = 1;
+
+    /// Converts the value at the given `index`. Returns [`LuaError::Type`] if the value cannot be converted to `Self`.
+    ///
+    /// This method must leave the stack unchanged.
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError>;
+}
+
+/// A trait to push a Rust value onto the stack.
+pub trait ToLua {
+    /// Pushes this value onto the stack and returns the number of values that were pushed. This is always 1 except
+    /// for `()` and tuples.
+    fn to_lua(self, lua: *mut LuaState) -> c_int;
+}
+
+impl FromLua for LuaValue {
+    const TYPE_NAME: &'static str = "value";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        let index = abs_index(lua, index);
+        let value = match (api().lua_type)(lua, index) {
+            LUA_TBOOLEAN => Self::Boolean(bool::from_lua(lua, index)?),
+            LUA_TLIGHTUSERDATA => Self::LightUserData((api().lua_touserdata)(lua, index) as _),
+            LUA_TNUMBER => {
+                if unsafe { (api().lua_isinteger)(lua, index) } != 0 {
+                    Self::Integer(i64::from_lua(lua, index)?)
+                } else {
+                    Self::Number(f64::from_lua(lua, index)?)
+                }
+            }
+            LUA_TSTRING => Self::String(to_bytes(lua, index).unwrap_or_default()),
+            LUA_TTABLE => Self::Table(TableRef::from_index(lua, index)?),
+            LUA_TFUNCTION => Self::Function(FunctionRef::from_index(lua, index)?),
+            LUA_TUSERDATA => Self::UserData(LuaRef::from_index(lua, index)),
+            LUA_TTHREAD => Self::Thread(LuaRef::from_index(lua, index)),
+            _ => Self::Nil,
+        };
+
+        Ok(value)
+    }
+}
+
+impl ToLua for LuaValue {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        match self {
+            Self::Nil => push_nil(lua),
+            Self::Boolean(v) => return v.to_lua(lua),
+            Self::Integer(v) => return v.to_lua(lua),
+            Self::Number(v) => return v.to_lua(lua),
+            Self::String(v) => unsafe {
+                (api().lua_pushlstring)(lua, v.as_ptr() as *const _, v.len());
+            },
+            Self::LightUserData(v) => (api().lua_pushlightuserdata)(lua, v as _),
+            Self::Table(v) => v.push(lua),
+            Self::Function(v) => v.push(lua),
+            Self::UserData(v) | Self::Thread(v) => {
+                v.push(lua);
+            }
+        }
+
+        1
+    }
+}
+
+impl FromLua for bool {
+    const TYPE_NAME: &'static str = "boolean";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        if (api().lua_type)(lua, index) == LUA_TBOOLEAN {
+            Ok(unsafe { (api().lua_toboolean)(lua, index) } != 0)
+        } else {
+            Err(LuaError::r#type(index, Self::TYPE_NAME))
+        }
+    }
+}
+
+impl ToLua for bool {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        unsafe { (api().lua_pushboolean)(lua, self.into()) };
+        1
+    }
+}
+
+macro_rules! integer {
+    ($($ty:ty),*) => {
+        $(
+            impl FromLua for $ty {
+                const TYPE_NAME: &'static str = "integer";
+
+                fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+                    let mut success: c_int = 0;
+                    let value = unsafe { (api().lua_tointegerx)(lua, index, &mut success) };
+
+                    if success == 0 {
+                        Err(LuaError::r#type(index, Self::TYPE_NAME))
+                    } else {
+                        value.try_into().map_err(|_| LuaError::argument(index, "integer out of range"))
+                    }
+                }
+            }
+
+            /// A value that does not fit in a Lua integer is pushed as a float, which may lose precision.
+            impl ToLua for $ty {
+                fn to_lua(self, lua: *mut LuaState) -> c_int {
+                    match i64::try_from(self) {
+                        Ok(v) => unsafe { (api().lua_pushinteger)(lua, v) },
+                        Err(_) => unsafe { (api().lua_pushnumber)(lua, self as _) },
+                    }
+
+                    1
+                }
+            }
+        )*
+    };
+}
+
+integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! number {
+    ($($ty:ty),*) => {
+        $(
+            impl FromLua for $ty {
+                const TYPE_NAME: &'static str = "number";
+
+                fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+                    let mut success: c_int = 0;
+                    let value = unsafe { (api().lua_tonumberx)(lua, index, &mut success) };
+
+                    if success == 0 {
+                        Err(LuaError::r#type(index, Self::TYPE_NAME))
+                    } else {
+                        Ok(value as _)
+                    }
+                }
+            }
+
+            impl ToLua for $ty {
+                fn to_lua(self, lua: *mut LuaState) -> c_int {
+                    unsafe { (api().lua_pushnumber)(lua, self.into()) };
+                    1
+                }
+            }
+        )*
+    };
+}
+
+number!(f32, f64);
+
+impl FromLua for String {
+    const TYPE_NAME: &'static str = "string";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        let value = to_bytes(lua, index).ok_or_else(|| LuaError::r#type(index, Self::TYPE_NAME))?;
+
+        String::from_utf8(value).map_err(|_| LuaError::argument(index, "invalid UTF-8 string"))
+    }
+}
+
+impl ToLua for String {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        self.as_str().to_lua(lua)
+    }
+}
+
+impl ToLua for &str {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        unsafe { (api().lua_pushlstring)(lua, self.as_ptr() as *const _, self.len()) };
+        1
+    }
+}
+
+impl<T: FromLua> FromLua for Option<T> {
+    const TYPE_NAME: &'static str = T::TYPE_NAME;
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        if (api().lua_type)(lua, index) <= LUA_TNIL {
+            Ok(None)
+        } else {
+            T::from_lua(lua, index).map(Some)
+        }
+    }
+}
+
+impl<T: ToLua> ToLua for Option<T> {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        match self {
+            Some(v) => v.to_lua(lua),
+            None => {
+                push_nil(lua);
+                1
+            }
+        }
+    }
+}
+
+impl<T: FromLua> FromLua for Vec<T> {
+    const TYPE_NAME: &'static str = "table";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        if (api().lua_type)(lua, index) != LUA_TTABLE {
+            return Err(LuaError::r#type(index, Self::TYPE_NAME));
+        }
+
+        let index = abs_index(lua, index);
+        let len = unsafe { (api().lua_rawlen)(lua, index) };
+        let mut result = Vec::with_capacity(len as _);
+
+        for i in 1..=len {
+            unsafe { (api().lua_rawgeti)(lua, index, i as _) };
+            let value = T::from_lua(lua, -1);
+            pop(lua, 1);
+
+            match value {
+                Ok(v) => result.push(v),
+                Err(_) => return Err(element_error(index, i, T::TYPE_NAME)),
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+impl<T: ToLua> ToLua for Vec<T> {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        create_table(lua, self.len() as _, 0);
+
+        for (i, v) in self.into_iter().enumerate() {
+            v.to_lua(lua);
+            unsafe { (api().lua_rawseti)(lua, -2, (i + 1) as _) };
+        }
+
+        1
+    }
+}
+
+impl<K, V> FromLua for HashMap<K, V>
+where
+    K: FromLua + Eq + Hash,
+    V: FromLua,
+{
+    const TYPE_NAME: &'static str = "table";
+
+    fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+        if (api().lua_type)(lua, index) != LUA_TTABLE {
+            return Err(LuaError::r#type(index, Self::TYPE_NAME));
+        }
+
+        let index = abs_index(lua, index);
+        let mut result = HashMap::new();
+
+        push_nil(lua);
+
+        while unsafe { (api().lua_next)(lua, index) } != 0 {
+            let k = match K::from_lua(lua, -2) {
+                Ok(v) => v,
+                Err(_) => {
+                    pop(lua, 2);
+                    return Err(LuaError::argument(
+                        index,
+                        format!("invalid key ({} expected)", K::TYPE_NAME),
+                    ));
+                }
+            };
+
+            let v = match V::from_lua(lua, -1) {
+                Ok(v) => v,
+                Err(_) => {
+                    pop(lua, 2);
+                    return Err(LuaError::argument(
+                        index,
+                        format!("invalid value ({} expected)", V::TYPE_NAME),
+                    ));
+                }
+            };
+
+            // The conversion may map different Lua keys to the same value (e.g. 1 and '1' for String).
+            if result.insert(k, v).is_some() {
+                let key = to_bytes(lua, -2).unwrap_or_default();
+                let key = String::from_utf8_lossy(&key).into_owned();
+
+                pop(lua, 2);
+
+                return Err(LuaError::argument(
+                    index,
+                    format!("duplicate key '{}' ({} expected)", key, K::TYPE_NAME),
+                ));
+            }
+
+            pop(lua, 1);
+        }
+
+        Ok(result)
+    }
+}
+
+impl<K: ToLua, V: ToLua> ToLua for HashMap<K, V> {
+    fn to_lua(self, lua: *mut LuaState) -> c_int {
+        create_table(lua, 0, self.len() as _);
+
+        for (k, v) in self {
+            k.to_lua(lua);
+            v.to_lua(lua);
+            unsafe { (api().lua_rawset)(lua, -3) };
+        }
+
+        1
+    }
+}
+
+/// A value that can be returned from the functions and the methods generated by `locenv-macros`.
+///
+/// This is implemented for the [`ToLua`] types of this crate, and for [`Option`], [`Vec`], [`HashMap`] and the tuples
+/// of any `Return` types. #\[object\] implements this for the object to push it as a new userdata with
+/// [`new_userdata()`](super::new_userdata), which may fail. Implement this with the same way if the object implements
+/// [`Object`](super::Object) manually, or with [`ToLua::to_lua()`] for your own [`ToLua`] type.
+pub trait Return {
+    /// Pushes the value onto the stack and returns the number of pushed values.
+    fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError>;
+}
+
+macro_rules! to_lua_return {
+    ($($ty:ty),*) => {
+        $(
+            impl Return for $ty {
+                fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+                    Ok(self.to_lua(lua))
+                }
+            }
+        )*
+    };
+}
+
+to_lua_return!(LuaValue, bool, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+to_lua_return!(String, &str, (), LuaRef, TableRef, FunctionRef, Table);
+
+impl<T: Return> Return for Option<T> {
+    fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+        match self {
+            Some(v) => v.into_lua(lua),
+            None => {
+                push_nil(lua);
+                Ok(1)
+            }
+        }
+    }
+}
+
+impl<T: Return> Return for Vec<T> {
+    fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+        create_table(lua, self.len() as _, 0);
+
+        for (i, v) in self.into_iter().enumerate() {
+            v.into_lua(lua)?;
+            unsafe { (api().lua_rawseti)(lua, -2, (i + 1) as _) };
+        }
+
+        Ok(1)
+    }
+}
+
+impl<K: ToLua, V: Return> Return for HashMap<K, V> {
+    fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+        create_table(lua, 0, self.len() as _);
+
+        for (k, v) in self {
+            k.to_lua(lua);
+            v.into_lua(lua)?;
+            unsafe { (api().lua_rawset)(lua, -3) };
+        }
+
+        Ok(1)
+    }
+}
+
+impl FromLua for () {
+    const TYPE_NAME: &'static str = "nothing";
+    const SLOTS: c_int = 0;
+
+    fn from_lua(_: *mut LuaState, _: c_int) -> Result<Self, LuaError> {
+        Ok(())
+    }
+}
+
+impl ToLua for () {
+    fn to_lua(self, _: *mut LuaState) -> c_int {
+        0
+    }
+}
+
+macro_rules! tuple {
+    ($($name:ident),+) => {
+        impl<$($name: FromLua),+> FromLua for ($($name,)+) {
+            const TYPE_NAME: &'static str = "values";
+            const SLOTS: c_int = 0 $(+ $name::SLOTS)+;
+
+            #[allow(non_snake_case, unused_assignments)]
+            fn from_lua(lua: *mut LuaState, index: c_int) -> Result<Self, LuaError> {
+                let mut index = abs_index(lua, index);
+
+                $(
+                    let $name = $name::from_lua(lua, index)?;
+                    index += $name::SLOTS;
+                )+
+
+                Ok(($($name,)+))
+            }
+        }
+
+        impl<$($name: ToLua),+> ToLua for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn to_lua(self, lua: *mut LuaState) -> c_int {
+                let ($($name,)+) = self;
+
+                0 $(+ $name.to_lua(lua))+
+            }
+        }
+
+        impl<$($name: Return),+> Return for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_lua(self, lua: *mut LuaState) -> Result<c_int, LuaError> {
+                let ($($name,)+) = self;
+
+                Ok(0 $(+ $name.into_lua(lua)?)+)
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
+tuple!(A, B, C, D, E, F, G);
+tuple!(A, B, C, D, E, F, G, H);
+
+pub(super) fn element_error(table: c_int, index: u64, expected: &str) -> LuaError {
+    LuaError::argument(
+        table,
+        format!("invalid element at index {} ({} expected)", index, expected),
+    )
+}
+
+/// Gets a copy of the string or number at the given `index` without modifying the stack.
+fn to_bytes(lua: *mut LuaState, index: c_int) -> Option<Vec<u8>> {
+    let r#type = (api().lua_type)(lua, index);
+
+    if r#type != LUA_TSTRING && r#type != LUA_TNUMBER {
+        return None;
+    }
+
+    // Convert a copy of the value so a number on the stack is not changed to a string, which will confuse lua_next.
+    let mut len = 0;
+
+    push_value(lua, index);
+
+    let data = unsafe { (api().lua_tolstring)(lua, -1, &mut len) };
+    let result = unsafe { from_raw_parts(data as *const u8, len) }.to_vec();
+
+    pop(lua, 1);
+
+    Some(result)
+}